@@ -1,6 +1,8 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("running prost codegen");
-    tonic_build::configure().build_server(false)
+    // server is needed only for mock of api
+    let mock = std::env::var("CARGO_FEATURE_MOCK").is_ok();
+    tonic_build::configure().build_server(mock).generate_default_stubs(mock)
         .protoc_arg("--experimental_allow_proto3_optional")
         .client_attribute(".", "#[derive(derive_more::From, derive_more::Into)]")
         .type_attribute("Quotation", "#[derive(Eq, Ord, PartialOrd)]")
@@ -0,0 +1,234 @@
+//! Backtesting of strategies by historical candles. Candles are replayed through [PaperApi],
+//! so strategy, written against [crate::InvestApi], works with history the same way as with real api.
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::Path;
+
+use prost::Message;
+
+use crate::paper::{PaperApi, PaperTrade};
+use crate::t_types::*;
+use crate::StreamResponse;
+
+/// Load candles, saved by [save_candles]
+pub fn load_candles(path: impl AsRef<Path>) -> std::io::Result<Vec<HistoricCandle>> {
+    let bytes = std::fs::read(path)?;
+    let res = GetCandlesResponse::decode(bytes.as_slice()).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    Ok(res.candles)
+}
+
+/// Save candles (e.g. from [GetCandlesResponse]) into file as protobuf
+pub fn save_candles(path: impl AsRef<Path>, candles: &[HistoricCandle]) -> std::io::Result<()> {
+    std::fs::write(path, GetCandlesResponse { candles: candles.to_vec() }.encode_to_vec())
+}
+
+/// Value of portfolio at the time
+#[derive(Debug, Clone, PartialEq)]
+pub struct EquityPoint {
+    pub time: prost_types::Timestamp,
+    pub value: Quotation,
+}
+
+/// Result of backtest
+#[derive(Debug, Clone, Default)]
+pub struct BacktestReport {
+    /// value of portfolio after each step
+    pub equity: Vec<EquityPoint>,
+    /// executed deals of account
+    pub trades: Vec<PaperTrade>,
+}
+
+impl BacktestReport {
+    /// Difference between last and first value of portfolio
+    pub fn profit(&self) -> Quotation {
+        match (self.equity.first(), self.equity.last()) {
+            (Some(first), Some(last)) => last.value - first.value,
+            _ => Default::default(),
+        }
+    }
+    /// Total commission of all deals
+    pub fn commission(&self) -> Quotation {
+        self.trades.iter().map(|t| t.commission).sum()
+    }
+    /// Max fall of portfolio value from its previous maximum
+    pub fn max_drawdown(&self) -> Quotation {
+        let mut max = None;
+        let mut drawdown = Quotation::default();
+        for point in &self.equity {
+            let max = max.get_or_insert(point.value);
+            if point.value > *max {
+                *max = point.value;
+            }
+            if *max - point.value > drawdown {
+                drawdown = *max - point.value;
+            }
+        }
+        drawdown
+    }
+}
+
+/// Driver of backtest. On each step simulated clock is moved to the next candle time, orders are matched
+/// by open price, then candles and last prices (by close price) are sent to [PaperApi::process].
+/// Orders execution is configured by [crate::paper::FillModel] of paper api.
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use yatis::backtest::{load_candles, Backtest};
+///     use yatis::paper::{FillModel, FillPrice, PaperApi};
+///     use t_types::*;
+/// #   let path = std::env::temp_dir().join(format!("yatis-backtest-doc-{}.pb", uuid::Uuid::new_v4()));
+/// #   let candle = |close: f64, seconds| HistoricCandle { open: Some(close.into()), close: Some(close.into()), time: Some(prost_types::Timestamp { seconds, nanos: 0 }), is_complete: true, ..Default::default() };
+/// #   backtest::save_candles(&path, &[candle(100.0, 0), candle(110.0, 60)]).unwrap();
+///     let api = PaperApi::new().with_account("paper", 1000.0)
+///         .with_fill_model(FillModel::new(FillPrice::Next).with_commission(0.0005));
+///     let backtest = Backtest::new(api, "paper")
+///         .with_candles("TCS80A107UL4", SubscriptionInterval::OneMinute, load_candles(&path).unwrap());
+/// #   let _ = std::fs::remove_file(&path);
+///     let report = backtest.run(|api, event| async move {
+///         if let StreamResponse::Candle(_) = event {
+///             let _ = api.request(PostOrderRequest {
+///                 instrument_id: "TCS80A107UL4".into(),
+///                 account_id: "paper".into(),
+///                 quantity: 1,
+///                 direction: OrderDirection::Buy.into(),
+///                 order_type: OrderType::Market.into(),
+///                 ..Default::default()
+///             }).await;
+///         }
+///     }).await;
+///     println!("profit: {}, deals: {}", report.profit(), report.trades.len());
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Backtest {
+    api: PaperApi,
+    account_id: String,
+    /// candles by time
+    candles: BTreeMap<(i64, i32), Vec<Candle>>,
+    time: std::option::Option<prost_types::Timestamp>,
+    equity: Vec<EquityPoint>,
+}
+
+impl Backtest {
+    /// Backtest of account of paper api
+    pub fn new(api: PaperApi, account_id: impl ToString) -> Self {
+        Self { api, account_id: account_id.to_string(), candles: BTreeMap::new(), time: None, equity: Vec::new() }
+    }
+    /// Add history of instrument. Incomplete candles are skipped
+    pub fn with_candles(mut self, instrument_id: impl ToString, interval: SubscriptionInterval, candles: impl IntoIterator<Item = HistoricCandle>) -> Self {
+        let instrument_id = instrument_id.to_string();
+        for c in candles.into_iter().filter(|c| c.is_complete) {
+            let time = c.time.unwrap_or_default();
+            self.candles.entry((time.seconds, time.nanos)).or_default().push(Candle {
+                figi: instrument_id.clone(),
+                interval: interval as i32,
+                open: c.open,
+                high: c.high,
+                low: c.low,
+                close: c.close,
+                volume: c.volume,
+                time: c.time,
+                last_trade_ts: None,
+                instrument_uid: instrument_id.clone(),
+            });
+        }
+        self
+    }
+    /// Paper api of backtest, used by strategy
+    pub fn api(&self) -> PaperApi {
+        self.api.clone()
+    }
+    /// Current simulated time
+    pub fn time(&self) -> std::option::Option<prost_types::Timestamp> {
+        self.time
+    }
+    /// Move to the next time of candles. Returns market data, sent to streams of paper api, or `None` when history is over
+    pub fn step(&mut self) -> std::option::Option<Vec<StreamResponse>> {
+        let (_, candles) = self.candles.pop_first()?;
+        let time = candles[0].time.unwrap_or_default();
+        self.time = Some(time);
+        self.api.set_time(time);
+        for candle in &candles {
+            if let Some(open) = candle.open {
+                self.api.set_price(&candle.instrument_uid, open);
+            }
+        }
+        let mut events = Vec::new();
+        for candle in candles {
+            let last_price = candle.close.map(|price| LastPrice {
+                figi: candle.figi.clone(),
+                price: Some(price),
+                time: candle.time,
+                instrument_uid: candle.instrument_uid.clone(),
+            });
+            events.push(StreamResponse::Candle(candle));
+            events.extend(last_price.map(StreamResponse::LastPrice));
+        }
+        for event in &events {
+            self.api.process(event);
+        }
+        if let Some(portfolio) = self.api.portfolio(&self.account_id) {
+            let value = portfolio.total_amount_portfolio.map(Into::into).unwrap_or_default();
+            self.equity.push(EquityPoint { time, value });
+        }
+        Some(events)
+    }
+    /// Run backtest till the end of history. Strategy is called for each event of market data, next step is made
+    /// after strategy is completed
+    pub async fn run<F, Fut>(mut self, mut strategy: F) -> BacktestReport
+    where F: FnMut(PaperApi, StreamResponse) -> Fut, Fut: Future<Output = ()> {
+        while let Some(events) = self.step() {
+            for event in events {
+                strategy(self.api.clone(), event).await;
+            }
+        }
+        self.report()
+    }
+    /// Equity curve and deals of account at the moment
+    pub fn report(&self) -> BacktestReport {
+        let trades = self.api.trades().into_iter().filter(|t| t.account_id == self.account_id).collect();
+        BacktestReport { equity: self.equity.clone(), trades }
+    }
+}
+
+#[tokio::test]
+async fn test_backtest() {
+    use crate::paper::{FillModel, FillPrice};
+    use crate::Requestor;
+    let candle = |open: f64, close: f64, seconds| HistoricCandle {
+        open: Some(open.into()),
+        close: Some(close.into()),
+        time: Some(prost_types::Timestamp { seconds, nanos: 0 }),
+        is_complete: true,
+        ..Default::default()
+    };
+    let path = std::env::temp_dir().join(format!("yatis-test-backtest-{}.pb", uuid::Uuid::new_v4()));
+    save_candles(&path, &[candle(100.0, 100.0, 0), candle(110.0, 120.0, 60), candle(90.0, 80.0, 120)]).unwrap();
+    let candles = load_candles(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let api = PaperApi::new().with_account("acc", 1000.0).with_fill_model(FillModel::new(FillPrice::Next).with_commission(0.01));
+    let report = Backtest::new(api, "acc").with_candles("x", SubscriptionInterval::OneMinute, candles)
+    .run(|api, event| async move {
+        let StreamResponse::Candle(c) = event else { return };
+        let direction = if c.time.unwrap().seconds == 0 { OrderDirection::Buy } else { OrderDirection::Sell };
+        let _ = api.request(PostOrderRequest {
+            instrument_id: "x".into(),
+            account_id: "acc".into(),
+            quantity: 5,
+            direction: direction.into(),
+            order_type: OrderType::Market.into(),
+            ..Default::default()
+        }).await;
+    }).await;
+    assert_eq!(report.trades.len(), 2);
+    assert_eq!(report.trades[0].price, 110.0.into());
+    assert_eq!(report.trades[1].price, 90.0.into());
+    assert_eq!(report.trades[1].time.seconds, 120);
+    assert_eq!(report.commission(), 10.0.into());
+    let values: Vec<f64> = report.equity.iter().map(|p| p.value.into()).collect();
+    assert_eq!(values, vec![1000.0, 1044.5, 890.0]);
+    assert_eq!(report.profit(), (-110.0).into());
+    assert_eq!(report.max_drawdown(), 154.5.into());
+}
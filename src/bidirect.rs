@@ -1,68 +1,306 @@
-use std::{collections::HashSet, sync::{Arc, Mutex}, time::Duration};
+//! Bidirectional market data stream and balanced pool of them. Subscriptions can be changed at runtime, all of them are restored after reconnect.
+use std::{collections::HashMap, marker::PhantomData, sync::{Arc, Mutex}, time::Duration};
 
 use async_channel::Sender;
 use tokio::task::JoinHandle;
 use futures::SinkExt;
 
-use crate::{t_types::{market_data_stream_service_client::MarketDataStreamServiceClient, *}, Api};
+use crate::requestor::OwnedSender;
+use crate::t_types::market_data_request::Payload;
+use crate::t_types::market_data_response::Payload as ResponsePayload;
+use crate::t_types::market_data_stream_service_client::MarketDataStreamServiceClient;
+use crate::t_types::{
+    Candle, CandleInstrument, CandleInterval, GetCandlesRequest, GetCandlesResponse, HistoricCandle, InfoInstrument, LastPriceInstrument, MarketDataRequest, MarketDataResponse, OrderBookInstrument, PingDelaySettings,
+    SubscribeCandlesRequest, SubscribeInfoRequest, SubscribeLastPriceRequest, SubscribeOrderBookRequest, SubscribeTradesRequest,
+    SubscriptionAction, TradeInstrument,
+};
+use crate::stream::ReconnectPolicy;
+use crate::{Api, StreamResponse};
 use log::{info, warn, error};
 
+/// Handle of bidirectional market data stream
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use t_types::*;
+/// #    let token = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
+///     let api = Api::create_invest_service(token).unwrap();
+///     let (s, mut r) = futures::channel::mpsc::channel::<StreamResponse>(10);
+///     let stream = MarketDataStream::create(api, s).await.unwrap();
+///     stream.send(SubscribeLastPriceRequest {
+///         subscription_action: SubscriptionAction::Subscribe.into(),
+///         instruments: vec![LastPriceInstrument {instrument_id: "TCS80A107UL4".to_string(), ..Default::default()}],
+///     }).await.unwrap();
+///     use futures::StreamExt;
+///     println!("{:?}", r.next().await);
+/// # }
+/// ```
+pub type MarketDataStream = StreamHolder<MarketDataRequest>;
 
+fn instrument_key(figi: &str, instrument_id: &str) -> String {
+    if instrument_id.is_empty() { figi.to_owned() } else { instrument_id.to_owned() }
+}
+
+/// Subscribe-request of single kind (candles, orderbooks, etc.)
+trait Subscription: Clone + Into<MarketDataRequest> {
+    type Instrument: Clone;
+    fn key(instrument: &Self::Instrument) -> (String, i32);
+    fn action(&self) -> SubscriptionAction;
+    fn instruments(&self) -> &[Self::Instrument];
+    /// Same request (and same action) with another list of instruments
+    fn part(&self, instruments: Vec<Self::Instrument>) -> Self;
+    /// Same request with Subscribe action and another list of instruments
+    fn with_instruments(&self, instruments: Vec<Self::Instrument>) -> Self;
+    /// Active subscriptions of this kind in cache
+    fn cached(cache: &MarketCache) -> &Subscriptions<Self>;
+}
+
+macro_rules! subscription_impl {
+    ($($req:ident($instrument:ident) in $field:ident = |$i:ident| $key:expr,)+) => {$(
+        impl Subscription for $req {
+            type Instrument = $instrument;
+            fn key($i: &$instrument) -> (String, i32) {
+                $key
+            }
+            fn action(&self) -> SubscriptionAction {
+                SubscriptionAction::try_from(self.subscription_action).unwrap_or_default()
+            }
+            fn instruments(&self) -> &[$instrument] {
+                &self.instruments
+            }
+            fn part(&self, instruments: Vec<$instrument>) -> Self {
+                let mut req = self.clone();
+                req.instruments = instruments;
+                req
+            }
+            fn with_instruments(&self, instruments: Vec<$instrument>) -> Self {
+                let mut req = self.part(instruments);
+                req.subscription_action = SubscriptionAction::Subscribe.into();
+                req
+            }
+            fn cached(cache: &MarketCache) -> &Subscriptions<Self> {
+                &cache.$field
+            }
+        }
+        impl From<$req> for MarketDataRequest {
+            fn from(value: $req) -> Self {
+                Self { payload: Some(Payload::$req(value)) }
+            }
+        }
+    )+}
+}
+
+subscription_impl![
+    SubscribeCandlesRequest(CandleInstrument) in candles = |i| (instrument_key(&i.figi, &i.instrument_id), i.interval),
+    SubscribeOrderBookRequest(OrderBookInstrument) in orderbooks = |i| (instrument_key(&i.figi, &i.instrument_id), i.depth),
+    SubscribeTradesRequest(TradeInstrument) in trades = |i| (instrument_key(&i.figi, &i.instrument_id), 0),
+    SubscribeInfoRequest(InfoInstrument) in info = |i| (instrument_key(&i.figi, &i.instrument_id), 0),
+    SubscribeLastPriceRequest(LastPriceInstrument) in lastprice = |i| (instrument_key(&i.figi, &i.instrument_id), 0),
+];
+
+/// Active subscriptions of single kind. Last subscribe request is used as template for resubscription
 #[derive(Debug)]
+struct Subscriptions<R: Subscription> {
+    template: Option<R>,
+    instruments: HashMap<(String, i32), R::Instrument>,
+}
+
+impl<R: Subscription> Default for Subscriptions<R> {
+    fn default() -> Self {
+        Self { template: None, instruments: HashMap::new() }
+    }
+}
+
+impl<R: Subscription> Subscriptions<R> {
+    fn process(&mut self, req: &R) {
+        match req.action() {
+            SubscriptionAction::Subscribe => {
+                self.template = Some(req.clone());
+                for i in req.instruments() {
+                    self.instruments.insert(R::key(i), i.clone());
+                }
+            }
+            SubscriptionAction::Unsubscribe => for i in req.instruments() {
+                self.instruments.remove(&R::key(i));
+            }
+            SubscriptionAction::Unspecified => warn!("unspecified SubscriptionAction"),
+        }
+    }
+    fn contains(&self, key: &(String, i32)) -> bool {
+        self.instruments.contains_key(key)
+    }
+    fn request(&self) -> Option<R> {
+        if self.instruments.is_empty() {
+            return None;
+        }
+        let instruments = self.instruments.values().cloned().collect();
+        self.template.as_ref().map(|t| t.with_instruments(instruments))
+    }
+}
+
+#[derive(Debug, Default)]
 struct MarketCache {
-    lastprice: HashSet<String>,
+    candles: Subscriptions<SubscribeCandlesRequest>,
+    orderbooks: Subscriptions<SubscribeOrderBookRequest>,
+    trades: Subscriptions<SubscribeTradesRequest>,
+    info: Subscriptions<SubscribeInfoRequest>,
+    lastprice: Subscriptions<SubscribeLastPriceRequest>,
+    ping_settings: Option<PingDelaySettings>,
 }
+
 impl MarketCache {
-    fn new() -> Self {
-        Self{lastprice: HashSet::new()}
-    }
-    fn market_req(&self) -> MarketDataRequest {
-        let instruments = self.lastprice.iter().map(|id|LastPriceInstrument{instrument_id: id.clone(), ..Default::default()}).collect();
-        MarketDataRequest {payload:Some(crate::t_types::market_data_request::Payload::SubscribeLastPriceRequest(
-            SubscribeLastPriceRequest {
-                subscription_action: SubscriptionAction::Subscribe.into(),
-                instruments
-            }
-        ))}
-    }
-    fn add_instruments(&mut self, inst: &[LastPriceInstrument]) {
-        let mut changed = false;
-        inst.iter().for_each(|LastPriceInstrument { figi, instrument_id }| {
-            self.lastprice.insert(instrument_id.clone());
-    });
-    }
-    fn remove_instruments(&mut self, inst: &[LastPriceInstrument]) {
-        inst.iter().for_each(|LastPriceInstrument { figi, instrument_id }|{
-            self.lastprice.remove(instrument_id);
-        });
+    fn len(&self) -> usize {
+        self.candles.instruments.len() + self.orderbooks.instruments.len() + self.trades.instruments.len()
+            + self.info.instruments.len() + self.lastprice.instruments.len()
+    }
+    /// requests to restore all active subscriptions
+    fn market_reqs(&self) -> Vec<MarketDataRequest> {
+        let mut reqs = Vec::new();
+        if let Some(settings) = self.ping_settings {
+            reqs.push(MarketDataRequest { payload: Some(Payload::PingSettings(settings)) });
+        }
+        reqs.extend(self.candles.request().map(Into::into));
+        reqs.extend(self.orderbooks.request().map(Into::into));
+        reqs.extend(self.trades.request().map(Into::into));
+        reqs.extend(self.info.request().map(Into::into));
+        reqs.extend(self.lastprice.request().map(Into::into));
+        reqs
     }
     fn process(&mut self, req: &MarketDataRequest) {
-        use crate::t_types::market_data_request::Payload;
-        if req.payload.is_none() {return};
-        match req.payload.as_ref().unwrap() {
-            Payload::SubscribeCandlesRequest(subscribe_candles_request) =>{},
-            Payload::SubscribeOrderBookRequest(subscribe_order_book_request) => {},
-            Payload::SubscribeTradesRequest(subscribe_trades_request) => {},
-            Payload::SubscribeInfoRequest(subscribe_info_request) => {},
-            Payload::SubscribeLastPriceRequest(SubscribeLastPriceRequest{ subscription_action, instruments }) => {
-                match SubscriptionAction::try_from(*subscription_action).unwrap_or_default() {
-                    SubscriptionAction::Subscribe => self.add_instruments(&instruments),
-                    SubscriptionAction::Unsubscribe => self.remove_instruments(&instruments),
-                    SubscriptionAction::Unspecified => {log::warn!("unspecified SubscriptionAction")},
+        match &req.payload {
+            Some(Payload::SubscribeCandlesRequest(x)) => self.candles.process(x),
+            Some(Payload::SubscribeOrderBookRequest(x)) => self.orderbooks.process(x),
+            Some(Payload::SubscribeTradesRequest(x)) => self.trades.process(x),
+            Some(Payload::SubscribeInfoRequest(x)) => self.info.process(x),
+            Some(Payload::SubscribeLastPriceRequest(x)) => self.lastprice.process(x),
+            Some(Payload::PingSettings(x)) => self.ping_settings = Some(*x),
+            Some(Payload::GetMySubscriptions(_)) | Some(Payload::Ping(_)) | None => {},
+        }
+    }
+}
+
+fn time_key(time: &Option<prost_types::Timestamp>) -> (i64, i32) {
+    time.map(|t| (t.seconds, t.nanos)).unwrap_or_default()
+}
+
+/// Same candle without `last_trade_ts`, which is absent in historic candles
+fn same_candle(a: &Candle, b: &Candle) -> bool {
+    time_key(&a.time) == time_key(&b.time) && a.volume == b.volume
+        && (&a.open, &a.high, &a.low, &a.close) == (&b.open, &b.high, &b.low, &b.close)
+}
+
+/// Max period of [GetCandlesRequest] for interval of candles (conservative limits of api)
+fn max_candles_period(interval: i32) -> Duration {
+    const MINUTE: u64 = 60;
+    const DAY: u64 = 24 * 60 * MINUTE;
+    let secs = match CandleInterval::try_from(interval).unwrap_or_default() {
+        CandleInterval::CandleInterval5Sec | CandleInterval::CandleInterval10Sec => 200 * MINUTE,
+        CandleInterval::CandleInterval30Sec => 20 * 60 * MINUTE,
+        CandleInterval::CandleInterval30Min => 2 * DAY,
+        CandleInterval::Hour => 7 * DAY,
+        CandleInterval::CandleInterval2Hour | CandleInterval::CandleInterval4Hour => 30 * DAY,
+        CandleInterval::Day => 365 * DAY,
+        CandleInterval::Week => 2 * 365 * DAY,
+        CandleInterval::Month => 10 * 365 * DAY,
+        _ => DAY,
+    };
+    Duration::from_secs(secs)
+}
+
+/// Last received candles, used to backfill candles missed during reconnect
+#[derive(Debug, Default)]
+struct CandleBackfill {
+    last: HashMap<(String, i32), Candle>,
+}
+
+impl CandleBackfill {
+    /// Remember candle from live data. Returns `false` for outdated or repeated candle, that should be skipped
+    fn track(&mut self, response: &MarketDataResponse) -> bool {
+        match &response.payload {
+            Some(ResponsePayload::Candle(candle)) => {
+                let key = (candle.instrument_uid.clone(), candle.interval);
+                if let Some(last) = self.last.get(&key) {
+                    if time_key(&candle.time) < time_key(&last.time) || same_candle(candle, last) {
+                        return false;
+                    }
+                }
+                self.last.insert(key, candle.clone());
+            }
+            Some(ResponsePayload::SubscribeCandlesResponse(res)) => for s in &res.candles_subscriptions {
+                if s.subscription_action == SubscriptionAction::Unsubscribe as i32 {
+                    self.last.remove(&(s.instrument_uid.clone(), s.interval));
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+    /// Requests of candles from last received candle of each instrument till `to`.
+    /// Period is split into requests not longer than max period of interval, requests of instrument are in order of time
+    fn requests(&self, to: prost_types::Timestamp) -> Vec<((String, i32), GetCandlesRequest)> {
+        let mut requests = Vec::new();
+        for (key, candle) in &self.last {
+            let Some(mut from) = candle.time else { continue };
+            let period = max_candles_period(candle.interval).as_secs() as i64;
+            while time_key(&Some(from)) < time_key(&Some(to)) {
+                let mut till = prost_types::Timestamp { seconds: from.seconds + period, nanos: from.nanos };
+                if time_key(&Some(till)) > time_key(&Some(to)) {
+                    till = to;
                 }
-            },
-            Payload::GetMySubscriptions(get_my_subscriptions) => {},
-            Payload::Ping(ping_request) => {},
-            Payload::PingSettings(ping_delay_settings) => {},
+                requests.push((key.clone(), GetCandlesRequest {
+                    instrument_id: Some(key.0.clone()),
+                    from: Some(from),
+                    to: Some(till),
+                    interval: candle.interval,
+                    ..Default::default()
+                }));
+                from = till;
+            }
+        }
+        requests
+    }
+    /// Complete candles newer than last received candle. Last candle is updated
+    fn backfill(&mut self, key: &(String, i32), candles: Vec<HistoricCandle>) -> Vec<Candle> {
+        let Some(last) = self.last.get_mut(key) else { return Vec::new() };
+        let mut result = Vec::new();
+        for c in candles.into_iter().filter(|c| c.is_complete) {
+            let candle = Candle {
+                figi: last.figi.clone(),
+                interval: last.interval,
+                open: c.open,
+                high: c.high,
+                low: c.low,
+                close: c.close,
+                volume: c.volume,
+                time: c.time,
+                last_trade_ts: None,
+                instrument_uid: last.instrument_uid.clone(),
+            };
+            if time_key(&candle.time) >= time_key(&last.time) && !same_candle(&candle, last) {
+                *last = candle.clone();
+                result.push(candle);
+            }
         }
+        result
     }
 }
 
+/// State, shared between handle and stream task
 #[derive(Debug)]
-pub struct StreamHolder<Req> {
+struct Shared<Req> {
+    cache: MarketCache,
+    /// sender of current connection, replaced on reconnect
     sender: Sender<Req>,
-    cache: Arc<Mutex<MarketCache>>,
-    api: Api,
+    /// enabled recovery of candles after reconnect
+    backfill: Option<CandleBackfill>,
+}
+
+/// Holder of bidirectional stream. Stream is stopped when holder is dropped.
+#[derive(Debug)]
+pub struct StreamHolder<Req> {
+    shared: Arc<Mutex<Shared<Req>>>,
     handle: JoinHandle<()>,
 }
 
@@ -71,66 +309,387 @@ impl<Req> Drop for StreamHolder<Req> {
         self.handle.abort();
     }
 }
+
 impl StreamHolder<MarketDataRequest>  {
-    pub async fn create<Res, S>(api: Api, mut broadcast: S) -> Result<Self, tonic::Status> where S: futures::Sink<Res> + Unpin + Send + 'static, Res: From<MarketDataResponse> + Send + 'static {
-    //pub async fn create<Res>(api: Api, Bcast(broadcast): Bcast<Res>) -> Result<Self, tonic::Status> where Res: From<MarketDataResponse> + Send + 'static {
-        use crate::t_types::market_data_request::Payload;
+    /// Open bidirectional market data stream. All responses are sent to `broadcast`.
+    pub async fn create<Res, S>(api: Api, broadcast: S) -> Result<Self, tonic::Status>
+    where S: futures::Sink<Res> + Unpin + Send + 'static, Res: From<MarketDataResponse> + Send + 'static {
+        Self::create_with_policy(api, broadcast, ReconnectPolicy::default()).await
+    }
+    /// Open bidirectional market data stream with custom policy of reconnects.
+    /// Stream is stopped, when policy gives up
+    pub async fn create_with_policy<Res, S>(api: Api, mut broadcast: S, policy: ReconnectPolicy) -> Result<Self, tonic::Status>
+    where S: futures::Sink<Res> + Unpin + Send + 'static, Res: From<MarketDataResponse> + Send + 'static {
         let timeout = Duration::from_secs(5);
-        let req = MarketDataRequest { payload: Some(Payload::Ping(Default::default())) };
-        let (sender,r) = async_channel::unbounded();
-        sender.send(req.clone()).await.unwrap();
-        
+        let ping = MarketDataRequest { payload: Some(Payload::Ping(Default::default())) };
+        let (sender, r) = async_channel::unbounded();
+        let _ = sender.try_send(ping.clone());
+
         let mut client = MarketDataStreamServiceClient::from(api.clone());
-        let mut receiver = client.market_data_stream(r.clone()).await?.into_inner();
-        let cache = Arc::new(Mutex::new(MarketCache::new()));
-        let inner = (sender.clone(), cache.clone());
+        let mut receiver = client.market_data_stream(r).await?.into_inner();
+        let shared = Arc::new(Mutex::new(Shared { cache: MarketCache::default(), sender, backfill: None }));
+        let inner = shared.clone();
         let handle = tokio::spawn(async move {
-            let (sender, cache) = inner;
+            let shared = inner;
+            let mut pinged = false;
+            let mut attempt = 0;
+            let mut outage = std::time::Instant::now();
             loop {
                 match tokio::time::timeout(timeout, receiver.message()).await {
                     Ok(Ok(Some(response))) => {
+                        pinged = false;
+                        if let Some(backfill) = shared.lock().unwrap().backfill.as_mut() {
+                            if !backfill.track(&response) { continue; }
+                        }
                         if broadcast.send(response.into()).await.is_err() { break; }
                         continue;
                     },
-                    Ok(Ok(None)) => {
-                        info!("none received, reconnecting");
-                    },
-                    Ok(Err(e)) => log::error!("err {e:?}, reconnecting..."),
-                    Err(_) => {
+                    Ok(Ok(None)) => warn!("none received, reconnecting..."),
+                    Ok(Err(e)) => error!("err {e:?}, reconnecting..."),
+                    Err(_) if !pinged => {
                         info!("timeout, send ping...");
-                        if r.is_empty() {
-                            sender.send(req.clone()).await.unwrap();
-                        }
+                        pinged = true;
+                        let _ = shared.lock().unwrap().sender.try_send(ping.clone());
                         continue;
                     }
+                    Err(_) => warn!("no answer on ping, reconnecting..."),
                 }
-
-                while r.is_empty() {
-                    sender.send(req.clone()).await.unwrap();
+                pinged = false;
+                if attempt == 0 {
+                    outage = std::time::Instant::now();
+                } else if policy.gives_up(attempt, outage.elapsed()) {
+                    error!("market bi-directional stream is not restored after {attempt} attempts");
+                    break;
+                } else {
+                    tokio::time::sleep(policy.delay(attempt)).await;
+                }
+                attempt += 1;
+                let (sender, r) = async_channel::unbounded();
+                {
+                    let mut shared = shared.lock().unwrap();
+                    shared.cache.market_reqs().into_iter().for_each(|req| { let _ = sender.try_send(req); });
+                    if sender.is_empty() {
+                        let _ = sender.try_send(ping.clone());
+                    }
+                    shared.sender = sender;
                 }
-                
-                match tokio::time::timeout(timeout, client.market_data_stream(r.clone())).await {
+                match tokio::time::timeout(timeout, client.market_data_stream(r)).await {
                     Ok(Ok(x)) => {
                         receiver = x.into_inner();
-                        let req = cache.lock().unwrap().market_req();
-                        sender.send(req).await.unwrap();
+                        attempt = 0;
+                        info!("market bi-directional stream reconnected");
+                        if backfill_candles(&api, &shared, &mut broadcast).await.is_err() { break; }
                     }
-                    Ok(Err(x)) => {
-                        error!("err on reconnect: {x:?}");
-                        tokio::time::sleep(timeout).await;
-                    },
+                    Ok(Err(x)) => error!("err on reconnect: {x:?}"),
                     Err(_) => warn!("timeout on reconnect"),
                 }
             };
-            log::info!("market bi-directional stream stopped!");
+            info!("market bi-directional stream stopped!");
         });
-        Ok(Self {api, cache, sender, handle})
+        Ok(Self {shared, handle})
+    }
+    /// Enable recovery of candles after reconnect: candles, closed during reconnect, are requested by [GetCandlesRequest]
+    /// and sent in time order before live data. Repeated candles are skipped
+    pub fn with_candle_backfill(self, enabled: bool) -> Self {
+        let mut shared = self.shared.lock().unwrap();
+        if enabled != shared.backfill.is_some() {
+            shared.backfill = enabled.then(CandleBackfill::default);
+        }
+        drop(shared);
+        self
+    }
+    /// Send request to stream. Subscriptions are remembered and restored on reconnect.
+    /// Returns error if stream is stopped.
+    pub async fn send(&self, req: impl Into<MarketDataRequest>) -> Result<(), tonic::Status> {
+        if self.handle.is_finished() {
+            return Err(tonic::Status::unavailable("market data stream is stopped"));
+        }
+        let req = req.into();
+        let mut shared = self.shared.lock().unwrap();
+        shared.cache.process(&req);
+        // on closed channel subscription will be restored by reconnect
+        let _ = shared.sender.try_send(req);
+        Ok(())
+    }
+    /// Requests, that describe all active subscriptions of stream
+    pub fn subscriptions(&self) -> Vec<MarketDataRequest> {
+        self.shared.lock().unwrap().cache.market_reqs()
     }
-    pub async fn send(&self, req: MarketDataRequest) {
-        self.cache.lock().unwrap().process(&req);
-        self.sender.send(req).await.unwrap();
+    /// Count of active subscriptions (instruments of all kinds)
+    pub fn subscriptions_count(&self) -> usize {
+        self.shared.lock().unwrap().cache.len()
+    }
+    fn has_subscription<R: Subscription>(&self, key: &(String, i32)) -> bool {
+        R::cached(&self.shared.lock().unwrap().cache).contains(key)
     }
     pub fn stop(&self) {
         self.handle.abort();
     }
 }
+
+/// Send candles, missed during reconnect, to broadcast. Returns error, if broadcast is closed
+async fn backfill_candles<Res, S>(api: &Api, shared: &Mutex<Shared<MarketDataRequest>>, broadcast: &mut S) -> Result<(), S::Error>
+where S: futures::Sink<Res> + Unpin, Res: From<MarketDataResponse> {
+    let requests = match shared.lock().unwrap().backfill.as_ref() {
+        Some(backfill) => backfill.requests(std::time::SystemTime::now().into()),
+        None => return Ok(()),
+    };
+    let mut responses = Vec::new();
+    let mut failed = Vec::new();
+    for (key, req) in requests {
+        // later candles of instrument are not requested after error, so there is no gap before them
+        if failed.contains(&key) {
+            continue;
+        }
+        match OwnedSender::<GetCandlesRequest, GetCandlesResponse>::send(api, req).await {
+            Ok(res) => responses.push((key, res.candles)),
+            Err(e) => {
+                warn!("cannot backfill candles of {}: {e:?}", key.0);
+                failed.push(key);
+            }
+        }
+    }
+    let mut candles = {
+        let mut shared = shared.lock().unwrap();
+        let Some(backfill) = shared.backfill.as_mut() else { return Ok(()) };
+        responses.into_iter().flat_map(|(key, candles)| backfill.backfill(&key, candles)).collect::<Vec<_>>()
+    };
+    candles.sort_by_key(|c| time_key(&c.time));
+    info!("{} candles backfilled after reconnect", candles.len());
+    for candle in candles {
+        broadcast.send(MarketDataResponse { payload: Some(ResponsePayload::Candle(candle)) }.into()).await?;
+    }
+    Ok(())
+}
+
+/// Default limit of subscriptions per bidirectional stream
+pub const SUBSCRIPTIONS_PER_STREAM: usize = 300;
+/// Default limit of bidirectional streams per token
+pub const STREAMS_PER_TOKEN: usize = 16;
+
+#[derive(Default)]
+struct PoolState {
+    streams: Vec<MarketDataStream>,
+    ping_settings: Option<PingDelaySettings>,
+}
+
+/// Balanced pool of bidirectional market data streams.
+/// Subscriptions are spread across streams, new stream is opened when all streams are full.
+/// After unsubscribe the least loaded stream is closed, if its subscriptions fit into other streams.
+/// All streams send responses to the same sink.
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use yatis::bidirect::MarketStreamPool;
+///     use t_types::*;
+/// #    let token = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
+///     let api = Api::create_invest_service(token).unwrap();
+///     let (s, mut r) = futures::channel::mpsc::channel::<StreamResponse>(10);
+///     let pool = MarketStreamPool::new(api, s).with_limits(2, 4);
+///     pool.send(SubscribeLastPriceRequest {
+///         subscription_action: SubscriptionAction::Subscribe.into(),
+///         instruments: ["TCS80A107UL4", "BBG004730N88", "BBG004731032"].into_iter()
+///             .map(|id|LastPriceInstrument {instrument_id: id.to_string(), ..Default::default()}).collect(),
+///     }).await.unwrap();
+///     assert_eq!(pool.streams_count().await, 2);
+///     use futures::StreamExt;
+///     println!("{:?}", r.next().await);
+/// # }
+/// ```
+pub struct MarketStreamPool<S, Res = StreamResponse> {
+    api: Api,
+    sink: S,
+    subscriptions_limit: usize,
+    streams_limit: usize,
+    reconnect: ReconnectPolicy,
+    candle_backfill: bool,
+    state: tokio::sync::Mutex<PoolState>,
+    _res: PhantomData<fn() -> Res>,
+}
+
+impl<S, Res> MarketStreamPool<S, Res>
+where S: futures::Sink<Res> + Clone + Unpin + Send + 'static, Res: From<MarketDataResponse> + Send + 'static {
+    /// Create empty pool. Streams are opened on demand.
+    pub fn new(api: Api, sink: S) -> Self {
+        Self {
+            api,
+            sink,
+            subscriptions_limit: SUBSCRIPTIONS_PER_STREAM,
+            streams_limit: STREAMS_PER_TOKEN,
+            reconnect: ReconnectPolicy::default(),
+            candle_backfill: false,
+            state: Default::default(),
+            _res: PhantomData,
+        }
+    }
+    /// Set limits of subscriptions per stream and count of streams
+    pub fn with_limits(mut self, subscriptions_per_stream: usize, streams: usize) -> Self {
+        self.subscriptions_limit = subscriptions_per_stream.max(1);
+        self.streams_limit = streams.max(1);
+        self
+    }
+    /// Set policy of reconnects for streams, opened after this call
+    pub fn with_reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = policy;
+        self
+    }
+    /// Enable recovery of candles after reconnect for streams, opened after this call. See [StreamHolder::with_candle_backfill]
+    pub fn with_candle_backfill(mut self, enabled: bool) -> Self {
+        self.candle_backfill = enabled;
+        self
+    }
+    /// Send request to the streams, that serve its instruments.
+    /// Returns `RESOURCE_EXHAUSTED` if subscriptions do not fit into limits, nothing is sent in this case.
+    pub async fn send(&self, req: impl Into<MarketDataRequest>) -> Result<(), tonic::Status> {
+        let mut state = self.state.lock().await;
+        self.route_request(&mut state, req.into()).await?;
+        self.rebalance(&mut state).await
+    }
+    /// Count of active subscriptions in all streams
+    pub async fn subscriptions_count(&self) -> usize {
+        self.state.lock().await.streams.iter().map(|s|s.subscriptions_count()).sum()
+    }
+    /// Count of opened streams
+    pub async fn streams_count(&self) -> usize {
+        self.state.lock().await.streams.len()
+    }
+
+    async fn open(&self, state: &mut PoolState) -> Result<usize, tonic::Status> {
+        let stream = MarketDataStream::create_with_policy(self.api.clone(), self.sink.clone(), self.reconnect.clone()).await?
+            .with_candle_backfill(self.candle_backfill);
+        if let Some(settings) = state.ping_settings {
+            stream.send(MarketDataRequest { payload: Some(Payload::PingSettings(settings)) }).await?;
+        }
+        state.streams.push(stream);
+        Ok(state.streams.len() - 1)
+    }
+    async fn route_request(&self, state: &mut PoolState, req: MarketDataRequest) -> Result<(), tonic::Status> {
+        match req.payload {
+            Some(Payload::SubscribeCandlesRequest(x)) => self.route(state, x).await,
+            Some(Payload::SubscribeOrderBookRequest(x)) => self.route(state, x).await,
+            Some(Payload::SubscribeTradesRequest(x)) => self.route(state, x).await,
+            Some(Payload::SubscribeInfoRequest(x)) => self.route(state, x).await,
+            Some(Payload::SubscribeLastPriceRequest(x)) => self.route(state, x).await,
+            payload => {
+                if let Some(Payload::PingSettings(settings)) = payload {
+                    state.ping_settings = Some(settings);
+                }
+                let req = MarketDataRequest { payload };
+                for stream in &state.streams {
+                    stream.send(req.clone()).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+    async fn route<R: Subscription>(&self, state: &mut PoolState, req: R) -> Result<(), tonic::Status> {
+        let subscribe = match req.action() {
+            SubscriptionAction::Subscribe => true,
+            SubscriptionAction::Unsubscribe => false,
+            SubscriptionAction::Unspecified => {
+                warn!("unspecified SubscriptionAction");
+                return Ok(());
+            }
+        };
+        let mut load: Vec<_> = state.streams.iter().map(|s|s.subscriptions_count()).collect();
+        let mut parts = vec![Vec::new(); state.streams.len()];
+        let mut assigned = HashMap::new();
+        for instrument in req.instruments() {
+            let key = R::key(instrument);
+            let existing = assigned.get(&key).copied()
+                .or_else(|| state.streams.iter().position(|s|s.has_subscription::<R>(&key)));
+            let idx = match existing {
+                Some(idx) => idx,
+                None if !subscribe => continue,
+                None => {
+                    let free = (0..load.len()).filter(|&i| load[i] < self.subscriptions_limit).min_by_key(|&i| load[i]);
+                    let idx = match free {
+                        Some(idx) => idx,
+                        None if state.streams.len() < self.streams_limit => {
+                            load.push(0);
+                            parts.push(Vec::new());
+                            self.open(state).await?
+                        }
+                        None => return Err(tonic::Status::resource_exhausted("all market data streams are full")),
+                    };
+                    load[idx] += 1;
+                    assigned.insert(key, idx);
+                    idx
+                }
+            };
+            parts[idx].push(instrument.clone());
+        }
+        for (stream, part) in state.streams.iter().zip(parts) {
+            if !part.is_empty() {
+                stream.send(req.part(part)).await?;
+            }
+        }
+        Ok(())
+    }
+    /// close least loaded streams, while their subscriptions fit into other streams
+    async fn rebalance(&self, state: &mut PoolState) -> Result<(), tonic::Status> {
+        loop {
+            let total: usize = state.streams.iter().map(|s|s.subscriptions_count()).sum();
+            if state.streams.len() <= total.div_ceil(self.subscriptions_limit) {
+                return Ok(());
+            }
+            let Some(idx) = (0..state.streams.len()).min_by_key(|&i| state.streams[i].subscriptions_count()) else {
+                return Ok(());
+            };
+            let stream = state.streams.remove(idx);
+            for req in stream.subscriptions() {
+                if !matches!(req.payload, Some(Payload::PingSettings(_))) {
+                    self.route_request(state, req).await?;
+                }
+            }
+            info!("market data stream closed by rebalancing, {} streams left", state.streams.len());
+        }
+    }
+}
+
+#[test]
+fn test_resubscribe() {
+    let mut cache = MarketCache::default();
+    let candle = |id: &str| CandleInstrument { instrument_id: id.to_string(), interval: 1, ..Default::default() };
+    cache.process(&SubscribeCandlesRequest {
+        subscription_action: SubscriptionAction::Subscribe.into(),
+        instruments: vec![candle("a"), candle("b")],
+        waiting_close: true,
+        ..Default::default()
+    }.into());
+    cache.process(&SubscribeCandlesRequest {
+        subscription_action: SubscriptionAction::Unsubscribe.into(),
+        instruments: vec![candle("a")],
+        ..Default::default()
+    }.into());
+    cache.process(&SubscribeLastPriceRequest {
+        subscription_action: SubscriptionAction::Unsubscribe.into(),
+        instruments: vec![LastPriceInstrument { instrument_id: "a".to_string(), ..Default::default() }],
+    }.into());
+    let reqs = cache.market_reqs();
+    assert_eq!(reqs.len(), 1);
+    let Some(Payload::SubscribeCandlesRequest(req)) = &reqs[0].payload else { panic!("candles expected") };
+    assert_eq!(req.instruments, vec![candle("b")]);
+    assert!(req.waiting_close);
+}
+
+#[test]
+fn test_candle_backfill() {
+    let time = |seconds| Some(prost_types::Timestamp { seconds, nanos: 0 });
+    let candle = |seconds, volume| Candle { instrument_uid: "uid".into(), interval: 1, time: time(seconds), volume, ..Default::default() };
+    let response = |c: &Candle| MarketDataResponse { payload: Some(ResponsePayload::Candle(c.clone())) };
+    let mut backfill = CandleBackfill::default();
+    assert!(backfill.track(&response(&candle(60, 10))));
+    assert!(!backfill.track(&response(&candle(60, 10))));
+    assert!(backfill.track(&response(&candle(60, 12))));
+    let requests = backfill.requests(time(60 + 2 * 86400 + 30).unwrap());
+    let periods: Vec<_> = requests.iter().map(|(_, r)| (r.from.unwrap().seconds, r.to.unwrap().seconds)).collect();
+    assert_eq!(periods, vec![(60, 60 + 86400), (60 + 86400, 60 + 2 * 86400), (60 + 2 * 86400, 60 + 2 * 86400 + 30)]);
+    let historic = |seconds, volume, is_complete| HistoricCandle { time: time(seconds), volume, is_complete, ..Default::default() };
+    let candles = backfill.backfill(&requests[0].0, vec![historic(60, 15, true), historic(120, 5, true), historic(180, 1, false)]);
+    assert_eq!(candles.iter().map(|c| (c.time.unwrap().seconds, c.volume)).collect::<Vec<_>>(), vec![(60, 15), (120, 5)]);
+    assert!(!backfill.track(&response(&Candle { last_trade_ts: time(119), ..candle(120, 5) })));
+    assert!(backfill.track(&response(&candle(180, 3))));
+}
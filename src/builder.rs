@@ -0,0 +1,154 @@
+//! Configurable creation of [Api] and [SandboxApi]
+use std::sync::Arc;
+use std::time::Duration;
+
+use tonic::client::Grpc;
+use tonic::codec::CompressionEncoding::Gzip as GZIP;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint};
+
+use crate::limits::{RateLimitService, TariffLimits};
+use crate::{Api, IService, SandboxApi, TokenInterceptor};
+
+pub const PROD_ENDPOINT: &str = "https://invest-public-api.tinkoff.ru";
+pub const SANDBOX_ENDPOINT: &str = "https://sandbox-invest-public-api.tinkoff.ru";
+
+/// Builder of api with custom connection settings
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use std::time::Duration;
+///     use yatis::*;
+///     use t_types::*;
+/// #    let token = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
+///     let api = ApiBuilder::new(token)
+///         .with_connect_timeout(Duration::from_secs(5))
+///         .with_timeout(Duration::from_secs(10))
+///         .with_user_agent("my-robot")
+///         .build()
+///         .unwrap();
+///     println!("{:?}", api.request(GetInfoRequest{}).await);
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ApiBuilder {
+    interceptor: TokenInterceptor,
+    endpoint: Option<String>,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    keepalive: Option<Duration>,
+    nodelay: bool,
+    user_agent: Option<String>,
+    ca_certificate: Option<Certificate>,
+    tariff_limits: Option<Arc<TariffLimits>>,
+}
+
+impl ApiBuilder {
+    pub fn new(token: impl ToString) -> Self {
+        Self::with_interceptor(TokenInterceptor::new(token))
+    }
+    /// Builder with configured metadata of requests
+    pub fn with_interceptor(interceptor: TokenInterceptor) -> Self {
+        Self {
+            interceptor,
+            endpoint: None,
+            connect_timeout: None,
+            timeout: None,
+            keepalive: None,
+            nodelay: true,
+            user_agent: None,
+            ca_certificate: None,
+            tariff_limits: None,
+        }
+    }
+    /// Uri of server. Plain `http://` uri connects without tls, e.g. to local mock server.
+    /// By default [PROD_ENDPOINT] for [Api] and [SANDBOX_ENDPOINT] for [SandboxApi]
+    pub fn with_endpoint(mut self, uri: impl ToString) -> Self {
+        self.endpoint = Some(uri.to_string());
+        self
+    }
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+    /// Deadline of every request
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    /// Interval of http2 keepalive pings
+    pub fn with_keepalive(mut self, interval: Duration) -> Self {
+        self.keepalive = Some(interval);
+        self
+    }
+    /// TCP_NODELAY, enabled by default
+    pub fn with_nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+    /// Name of application, sent in `user-agent`
+    pub fn with_user_agent(mut self, user_agent: impl ToString) -> Self {
+        self.user_agent = Some(user_agent.to_string());
+        self
+    }
+    /// Name of application in `x-app-name`, see [TokenInterceptor::with_app_name]
+    pub fn with_app_name(mut self, app_name: impl ToString) -> Self {
+        self.interceptor = self.interceptor.with_app_name(app_name);
+        self
+    }
+    /// Trust to additional CA certificate in PEM format
+    pub fn with_ca_certificate(mut self, pem: impl AsRef<[u8]>) -> Self {
+        self.ca_certificate = Some(Certificate::from_pem(pem));
+        self
+    }
+    /// Limits of user's tariff for requests and streams, see [RateLimitService::with_tariff_limits]
+    pub fn with_tariff_limits(mut self, limits: impl Into<Arc<TariffLimits>>) -> Self {
+        self.tariff_limits = Some(limits.into());
+        self
+    }
+    fn endpoint(&self, default: &'static str) -> Result<Endpoint, tonic::transport::Error> {
+        let mut endpoint = match &self.endpoint {
+            Some(uri) => Endpoint::from_shared(uri.clone())?,
+            None => Endpoint::from_static(default),
+        };
+        if endpoint.uri().scheme_str() != Some("http") {
+            let mut tls = ClientTlsConfig::new().with_native_roots();
+            if let Some(ca) = &self.ca_certificate {
+                tls = tls.ca_certificate(ca.clone());
+            }
+            endpoint = endpoint.tls_config(tls)?;
+        }
+        if let Some(timeout) = self.connect_timeout {
+            endpoint = endpoint.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        if let Some(interval) = self.keepalive {
+            endpoint = endpoint.http2_keep_alive_interval(interval).keep_alive_while_idle(true);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            endpoint = endpoint.user_agent(user_agent.clone())?;
+        }
+        Ok(endpoint.tcp_nodelay(self.nodelay))
+    }
+    pub(crate) fn service(&self, default_endpoint: &'static str) -> Result<IService, tonic::transport::Error> {
+        let channel = self.endpoint(default_endpoint)?.connect_lazy();
+        let service = RateLimitService::new(InterceptedService::new(channel, self.interceptor.clone()));
+        Ok(match &self.tariff_limits {
+            Some(limits) => service.with_tariff_limits(limits.clone()),
+            None => service,
+        })
+    }
+    /// Create api for production server. Connection is lazy, so errors are only in settings
+    pub fn build(&self) -> Result<Api, tonic::transport::Error> {
+        let serv = self.service(PROD_ENDPOINT)?;
+        Ok(Grpc::new(serv).accept_compressed(GZIP).send_compressed(GZIP))
+    }
+    /// Create api for sandbox server
+    pub fn build_sandbox(&self) -> Result<SandboxApi, tonic::transport::Error> {
+        let serv = self.service(SANDBOX_ENDPOINT)?;
+        Ok(SandboxApi::new(Grpc::new(serv).accept_compressed(GZIP).send_compressed(GZIP)))
+    }
+}
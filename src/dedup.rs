@@ -0,0 +1,199 @@
+//! Sink adapter, that removes repeated stream responses after reconnects and fail-overs and keeps timestamps monotonic
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use crate::t_types::Quotation;
+use crate::StreamResponse;
+
+/// Default count of remembered keys
+pub const DEDUP_WINDOW: usize = 10_000;
+
+type Time = (i64, i32);
+
+fn time(t: &Option<prost_types::Timestamp>) -> Time {
+    t.map(|t| (t.seconds, t.nanos)).unwrap_or_default()
+}
+
+fn price(p: &Option<Quotation>) -> (i64, i32) {
+    p.map(|p| (p.units, p.nano)).unwrap_or_default()
+}
+
+fn instrument(figi: &str, uid: &str) -> String {
+    if uid.is_empty() { figi.to_owned() } else { uid.to_owned() }
+}
+
+/// Natural key of response
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    /// instrument, time, direction, price, quantity, source (market trades have no id)
+    Trade(String, Time, i32, (i64, i32), i64, i32),
+    /// instrument, interval, time, volume (updates of current candle have the same time)
+    Candle(String, i32, Time, i64),
+    /// order id, trade id
+    OrderTrade(String, String),
+}
+
+/// Kind of response with timestamp, that must not decrease
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Trade,
+    Candle(i32),
+    LastPrice,
+    Orderbook(i32),
+    TradingStatus,
+}
+
+#[derive(Debug)]
+struct DedupState {
+    window: usize,
+    seen: HashSet<Key>,
+    order: VecDeque<Key>,
+    last_time: HashMap<(Kind, String), Time>,
+}
+
+impl DedupState {
+    fn new(window: usize) -> Self {
+        Self { window, seen: HashSet::new(), order: VecDeque::new(), last_time: HashMap::new() }
+    }
+    /// Remember key, returns `false` for already seen key
+    fn insert(&mut self, key: Key) -> bool {
+        if !self.seen.insert(key.clone()) {
+            return false;
+        }
+        self.order.push_back(key);
+        while self.order.len() > self.window {
+            if let Some(key) = self.order.pop_front() {
+                self.seen.remove(&key);
+            }
+        }
+        true
+    }
+    /// Returns `false` for response older than last response of the same instrument
+    fn monotonic(&mut self, kind: Kind, instrument: String, time: Time) -> bool {
+        let last = self.last_time.entry((kind, instrument)).or_default();
+        if time < *last {
+            return false;
+        }
+        *last = time;
+        true
+    }
+    /// Filter response, `None` for duplicate or outdated response
+    fn filter(&mut self, response: StreamResponse) -> Option<StreamResponse> {
+        match response {
+            StreamResponse::Trade(t) => {
+                let i = instrument(&t.figi, &t.instrument_uid);
+                let key = Key::Trade(i.clone(), time(&t.time), t.direction, price(&t.price), t.quantity, t.trade_source);
+                (self.insert(key) && self.monotonic(Kind::Trade, i, time(&t.time))).then_some(StreamResponse::Trade(t))
+            }
+            StreamResponse::Candle(c) => {
+                let i = instrument(&c.figi, &c.instrument_uid);
+                let key = Key::Candle(i.clone(), c.interval, time(&c.time), c.volume);
+                (self.insert(key) && self.monotonic(Kind::Candle(c.interval), i, time(&c.time))).then_some(StreamResponse::Candle(c))
+            }
+            StreamResponse::OrderTrades(mut t) => {
+                t.trades.retain(|trade| self.insert(Key::OrderTrade(t.order_id.clone(), trade.trade_id.clone())));
+                (!t.trades.is_empty()).then_some(StreamResponse::OrderTrades(t))
+            }
+            StreamResponse::LastPrice(p) => {
+                let i = instrument(&p.figi, &p.instrument_uid);
+                self.monotonic(Kind::LastPrice, i, time(&p.time)).then_some(StreamResponse::LastPrice(p))
+            }
+            StreamResponse::Orderbook(o) => {
+                let i = instrument(&o.figi, &o.instrument_uid);
+                self.monotonic(Kind::Orderbook(o.depth), i, time(&o.time)).then_some(StreamResponse::Orderbook(o))
+            }
+            StreamResponse::TradingStatus(s) => {
+                let i = instrument(&s.figi, &s.instrument_uid);
+                self.monotonic(Kind::TradingStatus, i, time(&s.time)).then_some(StreamResponse::TradingStatus(s))
+            }
+            response => Some(response),
+        }
+    }
+}
+
+/// Sink adapter, that skips repeated `Trade`, `Candle` and `OrderTrades` within sliding window of keys
+/// and responses with timestamp older than last response of the same instrument.
+/// Clones share state, so one adapter can be used for several streams (e.g. in [crate::bidirect::MarketStreamPool]).
+///
+/// Market trades have no id in api, so they are compared by all fields. Deliberately, separate trades
+/// with the same instrument, time (up to nanoseconds), direction, price, quantity and source are treated as one trade.
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use yatis::dedup::Dedup;
+///     use t_types::*;
+/// #    let token = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
+///     let api = Api::create_invest_service(token).unwrap();
+///     let (s, mut r) = futures::channel::mpsc::channel::<StreamResponse>(10);
+///     let _handle = api.start_stream(TradesStreamRequest::default(), Dedup::new(s)).await.unwrap();
+///     use futures::StreamExt;
+///     println!("{:?}", r.next().await);
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Dedup<S> {
+    inner: S,
+    state: Arc<Mutex<DedupState>>,
+}
+
+impl<S> Dedup<S> {
+    pub fn new(inner: S) -> Self {
+        Self::with_window(inner, DEDUP_WINDOW)
+    }
+    /// Adapter, that remembers last `window` keys
+    pub fn with_window(inner: S, window: usize) -> Self {
+        Self { inner, state: Arc::new(Mutex::new(DedupState::new(window.max(1)))) }
+    }
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S> futures::Sink<StreamResponse> for Dedup<S> where S: futures::Sink<StreamResponse> + Unpin {
+    type Error = S::Error;
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+    fn start_send(mut self: Pin<&mut Self>, item: StreamResponse) -> Result<(), Self::Error> {
+        let filtered = self.state.lock().unwrap().filter(item);
+        match filtered {
+            Some(response) => Pin::new(&mut self.inner).start_send(response),
+            None => Ok(()),
+        }
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[test]
+fn test_dedup() {
+    use crate::t_types::*;
+    let ts = |seconds| Some(prost_types::Timestamp { seconds, nanos: 0 });
+    let mut state = DedupState::new(2);
+    let trade = |seconds| StreamResponse::Trade(Trade { figi: "f".into(), time: ts(seconds), quantity: 1, ..Default::default() });
+    assert!(state.filter(trade(10)).is_some());
+    assert!(state.filter(trade(10)).is_none());
+    assert!(state.filter(trade(5)).is_none());
+    let order_trades = |ids: &[&str]| StreamResponse::OrderTrades(OrderTrades {
+        order_id: "o".into(),
+        trades: ids.iter().map(|id| OrderTrade { trade_id: id.to_string(), ..Default::default() }).collect(),
+        ..Default::default()
+    });
+    assert!(state.filter(order_trades(&["1"])).is_some());
+    let Some(StreamResponse::OrderTrades(t)) = state.filter(order_trades(&["1", "2"])) else { panic!("new trade expected") };
+    assert_eq!(t.trades.len(), 1);
+    // first trade is out of window
+    assert!(state.filter(trade(10)).is_some());
+    let price = |seconds| StreamResponse::LastPrice(LastPrice { figi: "f".into(), time: ts(seconds), ..Default::default() });
+    assert!(state.filter(price(10)).is_some());
+    assert!(state.filter(price(9)).is_none());
+    assert!(state.filter(StreamResponse::Ping).is_some());
+}
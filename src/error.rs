@@ -0,0 +1,236 @@
+//! Typed errors of investAPI. Server sends numeric code of error in message of status,
+//! and description of error in metadata `message`.
+use std::fmt::Display;
+use std::future::Future;
+
+use tonic::Code;
+
+use crate::requestor::Requestor;
+
+/// Category of error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCategory {
+    /// Token is missing, invalid or has no access to the operation
+    Auth,
+    /// Limits of requests or streams are exceeded
+    Limits,
+    /// Invalid arguments or objects not found
+    Validation,
+    /// Instrument is not available for trading now
+    MarketClosed,
+    /// Not enough money or assets for operation
+    InsufficientFunds,
+    /// Internal errors of server or network
+    Internal,
+}
+
+/// Error codes of investAPI. Full list see in [documentation](https://developer.tbank.ru/invest/intro/developer/errors)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// 30034: not enough balance for the deal
+    NotEnoughBalance,
+    /// 30042: not enough assets for the deal
+    NotEnoughAssets,
+    /// 30079: instrument is not available for trading
+    InstrumentNotAvailable,
+    /// 40002: insufficient privileges of token
+    InsufficientPrivileges,
+    /// 40003: token is missing or invalid
+    InvalidToken,
+    /// 50001: instrument not found
+    InstrumentNotFound,
+    /// 70001: internal error of server
+    InternalError,
+    /// 70002: internal network error
+    NetworkError,
+    /// 80001: limit of concurrent streams is exceeded
+    StreamLimitExceeded,
+    /// 80002: limit of requests per minute is exceeded
+    RequestLimitExceeded,
+    /// Other numeric code of investAPI
+    Other(u32),
+    /// Status without numeric code, e.g. transport errors
+    Unknown,
+}
+
+impl ErrorCode {
+    /// numeric code of error, if exists
+    pub fn code(&self) -> Option<u32> {
+        use ErrorCode::*;
+        Some(match self {
+            NotEnoughBalance => 30034,
+            NotEnoughAssets => 30042,
+            InstrumentNotAvailable => 30079,
+            InsufficientPrivileges => 40002,
+            InvalidToken => 40003,
+            InstrumentNotFound => 50001,
+            InternalError => 70001,
+            NetworkError => 70002,
+            StreamLimitExceeded => 80001,
+            RequestLimitExceeded => 80002,
+            Other(code) => *code,
+            Unknown => return None,
+        })
+    }
+    /// Status, like the one returned by server for this error
+    pub fn status(&self, message: &str) -> tonic::Status {
+        use tonic::Code;
+        let Some(code) = self.code() else { return tonic::Status::unknown(message.to_owned()) };
+        let grpc_code = match code / 10000 {
+            3 => Code::InvalidArgument,
+            4 if *self == ErrorCode::InvalidToken => Code::Unauthenticated,
+            4 => Code::PermissionDenied,
+            5 => Code::NotFound,
+            8 => Code::ResourceExhausted,
+            _ => Code::Internal,
+        };
+        let mut status = tonic::Status::new(grpc_code, code.to_string());
+        if let Ok(message) = message.parse() {
+            status.metadata_mut().insert("message", message);
+        }
+        status
+    }
+}
+
+impl From<u32> for ErrorCode {
+    fn from(code: u32) -> Self {
+        use ErrorCode::*;
+        match code {
+            30034 => NotEnoughBalance,
+            30042 => NotEnoughAssets,
+            30079 => InstrumentNotAvailable,
+            40002 => InsufficientPrivileges,
+            40003 => InvalidToken,
+            50001 => InstrumentNotFound,
+            70001 => InternalError,
+            70002 => NetworkError,
+            80001 => StreamLimitExceeded,
+            80002 => RequestLimitExceeded,
+            code => Other(code),
+        }
+    }
+}
+
+/// Error of investAPI with decoded code
+#[derive(Debug, Clone)]
+pub struct Error {
+    pub code: ErrorCode,
+    /// description of error from server
+    pub message: String,
+    /// value of `x-tracking-id`, needed for support requests
+    pub tracking_id: Option<String>,
+    /// original status
+    pub status: tonic::Status,
+}
+
+impl Error {
+    pub fn category(&self) -> ErrorCategory {
+        use ErrorCategory::*;
+        use ErrorCode::*;
+        match self.code {
+            NotEnoughBalance | NotEnoughAssets => InsufficientFunds,
+            InstrumentNotAvailable => MarketClosed,
+            code => match code.code().map(|c| c / 10000) {
+                Some(3) | Some(5) | Some(9) => Validation,
+                Some(4) => Auth,
+                Some(8) => Limits,
+                Some(_) => Internal,
+                None => match self.status.code() {
+                    Code::Unauthenticated | Code::PermissionDenied => Auth,
+                    Code::ResourceExhausted => Limits,
+                    Code::InvalidArgument | Code::NotFound | Code::AlreadyExists
+                    | Code::FailedPrecondition | Code::OutOfRange => Validation,
+                    _ => Internal,
+                }
+            }
+        }
+    }
+}
+
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        let meta = |key: &str| status.metadata().get(key).and_then(|v| v.to_str().ok()).map(ToOwned::to_owned);
+        let code = status.message().trim().parse::<u32>().map(Into::into).unwrap_or(ErrorCode::Unknown);
+        let message = meta("message").unwrap_or_else(|| status.message().to_owned());
+        let tracking_id = meta("x-tracking-id");
+        Self { code, message, tracking_id, status }
+    }
+}
+
+impl From<Error> for tonic::Status {
+    fn from(value: Error) -> Self {
+        value.status
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.code.code() {
+            Some(code) => write!(f, "{code}: {}", self.message)?,
+            None => write!(f, "{}: {}", self.status.code(), self.message)?,
+        }
+        if let Some(id) = &self.tracking_id {
+            write!(f, " (tracking id: {id})")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.status)
+    }
+}
+
+/// Opt-in extension of [Requestor] with typed errors
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use yatis::error::{ErrorCategory, TypedRequestor};
+///     use t_types::*;
+/// #    let token = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
+///     let api = Api::create_invest_service(token).unwrap();
+///     match api.typed_request(GetInfoRequest{}).await {
+///         Ok(info) => println!("{info:?}"),
+///         Err(e) if e.category() == ErrorCategory::Auth => println!("check your token: {e}"),
+///         Err(e) => println!("{e}"),
+///     }
+/// # }
+/// ```
+pub trait TypedRequestor<Req, Res>: Requestor<Req, Res> {
+    /// the same as [Requestor::request], but returns [Error]
+    fn typed_request(&self, req: Req) -> impl Future<Output = Result<Res, Error>> + Send;
+}
+
+impl<T: Requestor<Req, Res> + Sync, Req: Send, Res> TypedRequestor<Req, Res> for T {
+    fn typed_request(&self, req: Req) -> impl Future<Output = Result<Res, Error>> + Send {
+        let fut = self.request(req);
+        async move { fut.await.map_err(Error::from) }
+    }
+}
+
+#[test]
+fn test_decode_status() {
+    let mut status = tonic::Status::invalid_argument("30042");
+    status.metadata_mut().insert("message", "not enough assets".parse().unwrap());
+    status.metadata_mut().insert("x-tracking-id", "abc".parse().unwrap());
+    let e = Error::from(status);
+    assert_eq!(e.code, ErrorCode::NotEnoughAssets);
+    assert_eq!(e.category(), ErrorCategory::InsufficientFunds);
+    assert_eq!(e.message, "not enough assets");
+    assert_eq!(e.tracking_id.as_deref(), Some("abc"));
+    assert_eq!(e.to_string(), "30042: not enough assets (tracking id: abc)");
+
+    let e = Error::from(ErrorCode::NotEnoughBalance.status("not enough balance"));
+    assert_eq!((e.code, e.status.code(), e.message.as_str()), (ErrorCode::NotEnoughBalance, tonic::Code::InvalidArgument, "not enough balance"));
+
+    let e = Error::from(tonic::Status::resource_exhausted("80003"));
+    assert_eq!(e.code, ErrorCode::Other(80003));
+    assert_eq!(e.category(), ErrorCategory::Limits);
+
+    let e = Error::from(tonic::Status::unauthenticated("no token"));
+    assert_eq!(e.code, ErrorCode::Unknown);
+    assert_eq!(e.category(), ErrorCategory::Auth);
+}
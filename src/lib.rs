@@ -1,13 +1,16 @@
 #![doc = include_str!("../README.md")]
+use limits::RateLimitService;
 use requestor::AnyRequestor;
 use stream::AnyStream;
 use t_types::Quotation;
-use tonic::codec::CompressionEncoding::Gzip as GZIP;
 use tonic::service::interceptor::InterceptedService;
 use tonic::service::Interceptor;
-use tonic::transport::{Channel, ClientTlsConfig};
+use tonic::transport::Channel;
+use tonic::metadata::{Ascii, MetadataKey};
 use tonic::{Request, Status};
 use uuid::Uuid;
+use token::TokenProvider;
+use std::sync::Arc;
 use tonic::client::Grpc;
 
 /// reused tonic's Grpc type, with implementation of [crate::InvestApi]
@@ -17,9 +20,10 @@ use tonic::client::Grpc;
 /// # async fn main() {
 ///     use yatis::*;
 ///     use t_types::*;
-/// #    let token = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
-///     let api = Api::create_invest_service(token).unwrap();
-///     println!("{:?}", api.request(GetInfoRequest{}).await);;
+/// #   let mock = yatis::mock::MockServer::start().await.unwrap();
+/// #   mock.respond(GetInfoResponse::default());
+///     let api = mock.api(); // or Api::create_invest_service(token).unwrap()
+///     println!("{:?}", api.request(GetInfoRequest{}).await);
 /// # }
 /// ``` 
 pub type Api = Grpc<IService>;
@@ -41,17 +45,38 @@ pub type Api = Grpc<IService>;
 pub use sandbox::Sandbox as SandboxApi;
 
 pub use pool::ApiPool;
+pub use error::Error;
+pub use builder::ApiBuilder;
 
 pub mod t_types;
 pub mod requestor;
 pub mod stream;
 pub mod stream_response;
 pub mod pool;
+pub mod bidirect;
+pub mod limits;
+pub mod error;
+pub mod retry;
+pub mod builder;
+pub mod token;
+pub mod router;
+pub mod recovery;
+pub mod dedup;
+pub mod paper;
+pub mod backtest;
+pub mod record;
+pub mod vcr;
+#[cfg(feature = "mock")]
+pub mod mock;
 
 mod sandbox;
 mod quotation;
 
-pub type IService = InterceptedService<Channel, TokenInterceptor>;
+/// Service with token and limits of server.
+///
+/// **Breaking change:** since `0.3.0` it is wrapped in [RateLimitService], before it was plain
+/// `InterceptedService<Channel, TokenInterceptor>`. Code, that names inner type of [Api], must use this alias.
+pub type IService = RateLimitService<InterceptedService<Channel, TokenInterceptor>>;
 /// Self creator
 pub trait InvestService: Sized {
     fn create_invest_service(token: impl ToString) -> Result<Self, tonic::transport::Error>;
@@ -59,44 +84,106 @@ pub trait InvestService: Sized {
 
 impl InvestService for IService {
     fn create_invest_service(token: impl ToString) -> Result<Self, tonic::transport::Error> {
-        let tls = ClientTlsConfig::new().with_native_roots();
-        let channel = Channel::from_static("https://invest-public-api.tinkoff.ru").tls_config(tls)?.connect_lazy();
-        Ok(InterceptedService::new(channel, TokenInterceptor::new(token)))
+        ApiBuilder::new(token).service(builder::PROD_ENDPOINT)
     }
 }
 impl InvestService for Grpc<IService> {
     fn create_invest_service(token: impl ToString) -> Result<Self, tonic::transport::Error> {
-        let serv = InterceptedService::create_invest_service(token)?;
-        let res = Grpc::new(serv).accept_compressed(GZIP).send_compressed(GZIP);
-        Ok(res)
+        ApiBuilder::new(token).build()
     }
 }
 
-#[derive(Debug, Clone)]
+/// Interceptor, that adds token and other metadata to requests
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use t_types::*;
+/// #   let mock = yatis::mock::MockServer::start().await.unwrap();
+/// #   mock.respond(GetInfoResponse::default());
+/// #   let token = "token";
+///     let interceptor = TokenInterceptor::new(token)
+///         .with_app_name("bool-rus.my-robot")
+///         .with_header("x-robot-id", "42")
+///         .with_tracking_id(|| format!("my-robot-{}", std::process::id()));
+///     let api = ApiBuilder::with_interceptor(interceptor)
+/// #       .with_endpoint(format!("http://{}", mock.addr()))
+///         .build().unwrap();
+///     let res = api.request_with_meta(GetInfoRequest{}).await.unwrap();
+///     assert_eq!(res.tracking_id, Some(format!("my-robot-{}", std::process::id())));
+/// # }
+/// ```
+#[derive(Clone)]
 pub struct TokenInterceptor {
-    token: String,
+    token: Arc<dyn TokenProvider>,
+    app_name: Option<String>,
+    headers: Vec<(String, String)>,
+    tracking_id: Option<Arc<dyn Fn() -> String + Send + Sync>>,
 }
 
 impl TokenInterceptor {
     pub fn new(token: impl ToString) -> Self {
-        Self { token: token.to_string() }
+        Self::with_provider(token.to_string())
+    }
+    /// Interceptor, that reads token from provider on every request, see [token]
+    pub fn with_provider(provider: impl TokenProvider + 'static) -> Self {
+        Self { token: Arc::new(provider), app_name: None, headers: Vec::new(), tracking_id: None }
+    }
+    /// Name of application in `x-app-name`, recommended format is `<github login>.<repository name>`
+    pub fn with_app_name(mut self, app_name: impl ToString) -> Self {
+        self.app_name = Some(app_name.to_string());
+        self
+    }
+    /// Static header for all requests
+    pub fn with_header(mut self, key: impl ToString, value: impl ToString) -> Self {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+    /// Generator of `x-tracking-id` for every request. By default it is random uuid
+    pub fn with_tracking_id(mut self, f: impl Fn() -> String + Send + Sync + 'static) -> Self {
+        self.tracking_id = Some(Arc::new(f));
+        self
+    }
+}
+
+impl std::fmt::Debug for TokenInterceptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenInterceptor")
+            .field("app_name", &self.app_name)
+            .field("headers", &self.headers)
+            .finish_non_exhaustive()
     }
 }
 
 impl Interceptor for TokenInterceptor {
     fn call(&mut self, mut req: Request<()>) -> Result<Request<()>, Status> {
-        req.metadata_mut().append(
-            "authorization",
-            format!("bearer {}", self.token).parse().unwrap(),
-        );
-        req.metadata_mut()
-            .append("x-tracking-id", Uuid::new_v4().to_string().parse().unwrap());
+        let invalid = |v: &str| Status::invalid_argument(format!("invalid metadata: {v}"));
+        let meta = req.metadata_mut();
+        let token = self.token.token().ok_or_else(|| Status::unauthenticated("token is not available"))?;
+        meta.append("authorization", format!("bearer {token}").parse().map_err(|_| invalid("authorization"))?);
+        let tracking_id = match &self.tracking_id {
+            Some(f) => f(),
+            None => Uuid::new_v4().to_string(),
+        };
+        meta.append("x-tracking-id", tracking_id.parse().map_err(|_| invalid(&tracking_id))?);
+        if let Some(app_name) = &self.app_name {
+            meta.append("x-app-name", app_name.parse().map_err(|_| invalid(app_name))?);
+        }
+        for (k, v) in &self.headers {
+            let key = MetadataKey::<Ascii>::from_bytes(k.as_bytes()).map_err(|_| invalid(k))?;
+            meta.append(key, v.parse().map_err(|_| invalid(v))?);
+        }
+        if let Some(sent) = req.extensions().get::<requestor::SentTrackingId>() {
+            sent.set(&tracking_id);
+        }
         Ok(req)
     }
 }
 
 pub use requestor::Requestor;
 pub use stream::StartStream;
+pub use bidirect::MarketDataStream;
 pub use stream_response::StreamResponse;
 
 /// trait for use some methods of investing api.
@@ -105,9 +192,18 @@ pub use stream_response::StreamResponse;
 /// # #[tokio::main]
 /// # async fn main() {
 /// #    use yatis::*;
-/// #    let token = std::env::var("SANDBOX_TOKEN").expect("need to set env var 'TOKEN'");
-/// #    let api = yatis::SandboxApi::create_invest_service(token).unwrap();
-/// #    trading(api).await;
+/// #    use t_types::*;
+/// #    let mock = std::sync::Arc::new(yatis::mock::MockServer::start().await.unwrap());
+/// #    mock.respond(GetAccountsResponse { accounts: vec![Account { id: "acc".into(), ..Default::default() }] });
+/// #    mock.respond(GetInfoResponse::default());
+/// #    let pusher = mock.clone();
+/// #    tokio::spawn(async move {
+/// #        while pusher.streams::<PositionsStreamResponse>() == 0 {
+/// #            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+/// #        }
+/// #        pusher.push(PositionsStreamResponse::default());
+/// #    });
+/// #    trading(mock.api()).await;
 /// # }
 /// async fn trading(api: impl yatis::InvestApi) {
 ///    use yatis::*;
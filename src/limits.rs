@@ -0,0 +1,569 @@
+//! Client-side limits of requests and connections, manual or from user's tariff (see [GetUserTariffRequest])
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, TryAcquireError};
+use tonic::codegen::{http, Body, BoxFuture, Service};
+
+use crate::requestor::{AnyRequestor, OwnedSender, Response};
+use crate::stream::{AnyStream, ReconnectPolicy, StartStream, StreamHandle};
+use crate::StreamResponse;
+use crate::t_types::{GetUserTariffRequest, GetUserTariffResponse, UnaryLimit};
+
+const PACKAGE: &str = "tinkoff.public.invest.api.contract.v1";
+const MINUTE: Duration = Duration::from_secs(60);
+
+/// Full grpc name of method by names of generated client and its method.
+/// For example `UsersServiceClient`, `get_info` gives `tinkoff.public.invest.api.contract.v1.UsersService/GetInfo`
+pub(crate) fn grpc_method(client: &str, method: &str) -> String {
+    let service = client.strip_suffix("Client").unwrap_or(client);
+    let method: String = method.split('_').map(|word| {
+        let mut chars = word.chars();
+        chars.next().map(|first| first.to_ascii_uppercase().to_string() + chars.as_str()).unwrap_or_default()
+    }).collect();
+    format!("{PACKAGE}.{service}/{method}")
+}
+
+/// Names of services, used to limit all methods of service
+pub mod service {
+    pub const INSTRUMENTS: &str = "tinkoff.public.invest.api.contract.v1.InstrumentsService";
+    pub const MARKET_DATA: &str = "tinkoff.public.invest.api.contract.v1.MarketDataService";
+    pub const OPERATIONS: &str = "tinkoff.public.invest.api.contract.v1.OperationsService";
+    pub const ORDERS: &str = "tinkoff.public.invest.api.contract.v1.OrdersService";
+    pub const STOP_ORDERS: &str = "tinkoff.public.invest.api.contract.v1.StopOrdersService";
+    pub const USERS: &str = "tinkoff.public.invest.api.contract.v1.UsersService";
+    pub const SIGNALS: &str = "tinkoff.public.invest.api.contract.v1.SignalService";
+    pub const SANDBOX: &str = "tinkoff.public.invest.api.contract.v1.SandboxService";
+}
+
+/// Token bucket: `per_minute` tokens, refilled evenly during minute
+#[derive(Debug)]
+struct TokenBucket {
+    per_minute: usize,
+    /// available tokens and time of last refill. Negative tokens are reserved by waiting requests
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    fn new(per_minute: usize) -> Self {
+        Self { per_minute, state: Mutex::new((per_minute as f64, Instant::now())) }
+    }
+    fn rate(&self) -> f64 {
+        self.per_minute as f64 / MINUTE.as_secs_f64()
+    }
+    /// take token, returns time to wait for it
+    fn reserve(&self) -> Duration {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let (tokens, last) = *state;
+        let tokens = (tokens + now.duration_since(last).as_secs_f64() * self.rate()).min(self.per_minute as f64) - 1.0;
+        *state = (tokens, now);
+        if tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-tokens / self.rate())
+        }
+    }
+    /// waits until request is allowed
+    async fn acquire(&self) {
+        let wait = self.reserve();
+        if !wait.is_zero() {
+            log::debug!("rate limit exceeded, waiting {wait:?}");
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Limiter of unary requests with token buckets per method, group of methods or service.
+/// Method limit has priority over service limit. Methods without limits are not limited, limit `0` means no limit.
+/// # Examples:
+/// ```rust
+/// use yatis::limits::{service, RateLimiter};
+/// let limiter = RateLimiter::new()
+///     .with_service_limit(service::MARKET_DATA, 600)
+///     .with_method_limit("tinkoff.public.invest.api.contract.v1.MarketDataService/GetCandles", 300);
+/// ```
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    methods: HashMap<String, Arc<TokenBucket>>,
+    services: HashMap<String, Arc<TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Limit requests per minute of single method, e.g. `tinkoff.public.invest.api.contract.v1.UsersService/GetInfo`
+    pub fn with_method_limit(self, method: impl ToString, per_minute: usize) -> Self {
+        self.with_group_limit([method], per_minute)
+    }
+    /// Limit requests per minute of group of methods. Requests of all methods take tokens from the same bucket
+    pub fn with_group_limit(mut self, methods: impl IntoIterator<Item = impl ToString>, per_minute: usize) -> Self {
+        if per_minute == 0 {
+            return self;
+        }
+        let bucket = Arc::new(TokenBucket::new(per_minute));
+        for method in methods {
+            self.methods.insert(method.to_string(), bucket.clone());
+        }
+        self
+    }
+    /// Limit requests per minute of all methods of service, e.g. [service::MARKET_DATA]
+    pub fn with_service_limit(mut self, service: impl ToString, per_minute: usize) -> Self {
+        if per_minute == 0 {
+            return self;
+        }
+        self.services.insert(service.to_string(), Arc::new(TokenBucket::new(per_minute)));
+        self
+    }
+    fn bucket(&self, method: &str) -> Option<&TokenBucket> {
+        let service = method.split('/').next().unwrap_or_default();
+        self.methods.get(method).or_else(|| self.services.get(service)).map(|b| b.as_ref())
+    }
+    /// Requests per minute for method, if limited
+    pub fn limit(&self, method: &str) -> Option<usize> {
+        self.bucket(method).map(|b| b.per_minute)
+    }
+    /// Waits until request of method is allowed
+    pub async fn acquire(&self, method: &str) {
+        if let Some(bucket) = self.bucket(method) {
+            bucket.acquire().await;
+        }
+    }
+}
+
+impl From<Vec<UnaryLimit>> for RateLimiter {
+    fn from(value: Vec<UnaryLimit>) -> Self {
+        value.into_iter()
+            .filter(|l| l.limit_per_minute > 0)
+            .fold(Self::new(), |limiter, l| limiter.with_group_limit(l.methods, l.limit_per_minute as usize))
+    }
+}
+
+impl From<GetUserTariffResponse> for RateLimiter {
+    fn from(value: GetUserTariffResponse) -> Self {
+        value.unary_limits.into()
+    }
+}
+
+/// Wrapper of any requestor, that waits for [RateLimiter] before each request. Streams are passed as is.
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use yatis::limits::{service, RateLimited, RateLimiter};
+///     use t_types::*;
+/// #    let token = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
+///     let api = Api::create_invest_service(token).unwrap();
+///     let api = RateLimited::new(api, RateLimiter::new().with_service_limit(service::MARKET_DATA, 60));
+///     trading(api).await;
+/// # }
+/// async fn trading(api: impl yatis::InvestApi) {
+///     use yatis::*;
+///     println!("{:?}", api.request(t_types::GetInfoRequest{}).await);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RateLimited<T> {
+    inner: T,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<T> RateLimited<T> {
+    /// The same limiter can be shared between several wrappers with the same token
+    pub fn new(inner: T, limiter: impl Into<Arc<RateLimiter>>) -> Self {
+        Self { inner, limiter: limiter.into() }
+    }
+    /// Wrapper with limits of user's tariff
+    pub async fn with_tariff(inner: T) -> Result<Self, tonic::Status> where T: OwnedSender<GetUserTariffRequest, GetUserTariffResponse> {
+        let tariff = inner.send(GetUserTariffRequest {}).await?;
+        Ok(Self::new(inner, RateLimiter::from(tariff)))
+    }
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+    pub fn limiter(&self) -> &Arc<RateLimiter> {
+        &self.limiter
+    }
+}
+
+impl<T, Req, Res> OwnedSender<Req, Res> for RateLimited<T> where T: OwnedSender<Req, Res> + Send + Sync, Req: Send, Res: Send {
+    fn send_and_back(self, req: Req) -> impl Future<Output = (Self, Result<Res, tonic::Status>)> + Send {
+        Box::pin(async move {
+            self.limiter.acquire(T::method_name()).await;
+            let Self { inner, limiter } = self;
+            let (inner, res) = inner.send_and_back(req).await;
+            (Self { inner, limiter }, res)
+        })
+    }
+    fn send(&self, req: Req) -> impl Future<Output = Result<Res, tonic::Status>> + Send {
+        Box::pin(async move {
+            self.limiter.acquire(T::method_name()).await;
+            self.inner.send(req).await
+        })
+    }
+    fn send_with_meta(&self, req: Req) -> impl Future<Output = Result<Response<Res>, tonic::Status>> + Send {
+        Box::pin(async move {
+            self.limiter.acquire(T::method_name()).await;
+            self.inner.send_with_meta(req).await
+        })
+    }
+    fn method_name() -> &'static str {
+        T::method_name()
+    }
+}
+
+impl<T, Req, X> StartStream<Req, X> for RateLimited<T> where T: StartStream<Req, X> + Sync, Req: Send {
+    fn start_stream<S>(&self, req: Req, sender: S) -> impl Future<Output = Result<StreamHandle<Req>, tonic::Status>> + Send
+    where S: futures::Sink<X> + Unpin + Send + 'static {
+        self.inner.start_stream(req, sender)
+    }
+    fn start_stream_with_policy<S>(&self, req: Req, sender: S, policy: ReconnectPolicy) -> impl Future<Output = Result<StreamHandle<Req>, tonic::Status>> + Send
+    where S: futures::Sink<X> + Unpin + Send + 'static {
+        self.inner.start_stream_with_policy(req, sender, policy)
+    }
+    fn stream_name() -> &'static str {
+        T::stream_name()
+    }
+}
+
+impl<T: AnyRequestor + Sync> AnyRequestor for RateLimited<T> {}
+impl<T: AnyStream<StreamResponse> + Sync> AnyStream<StreamResponse> for RateLimited<T> {}
+
+/// Last state of server limit of method, from `x-ratelimit-*` headers
+#[derive(Debug, Clone, Copy)]
+pub struct ServerLimitState {
+    pub limit: Option<u64>,
+    pub remaining: u64,
+    pub reset: Instant,
+}
+
+/// Server limits of methods from `x-ratelimit-*` response headers.
+/// When no requests remain, next requests of method wait until reset time.
+#[derive(Debug, Default)]
+pub struct ServerRateLimit {
+    methods: Mutex<HashMap<String, ServerLimitState>>,
+}
+
+impl ServerRateLimit {
+    /// Last known state of method limit
+    pub fn state(&self, method: &str) -> Option<ServerLimitState> {
+        self.methods.lock().unwrap().get(method).copied()
+    }
+    /// Waits until request of method is allowed by server and takes one of remaining requests,
+    /// so concurrent requests don't exceed the limit before response with new state
+    pub async fn wait(&self, method: &str) {
+        loop {
+            let reset = {
+                let mut methods = self.methods.lock().unwrap();
+                let Some(state) = methods.get_mut(method) else { return };
+                if state.reset <= Instant::now() {
+                    return;
+                }
+                if state.remaining > 0 {
+                    state.remaining -= 1;
+                    return;
+                }
+                state.reset
+            };
+            log::debug!("server limit of {method} exceeded, waiting for reset");
+            tokio::time::sleep_until(reset.into()).await;
+        }
+    }
+    /// Update limit of method from response headers
+    pub fn update(&self, method: &str, headers: &http::HeaderMap) {
+        // values can be like `100, 100;w=60`, first number is used
+        let header = |name: &str| headers.get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split([',', ';']).next())
+            .and_then(|v| v.trim().parse::<u64>().ok());
+        let (Some(remaining), Some(reset)) = (header("x-ratelimit-remaining"), header("x-ratelimit-reset")) else {
+            return;
+        };
+        let state = ServerLimitState { limit: header("x-ratelimit-limit"), remaining, reset: Instant::now() + Duration::from_secs(reset) };
+        self.methods.lock().unwrap().insert(method.to_owned(), state);
+    }
+}
+
+/// Service layer, that applies [ServerRateLimit] to all requests of inner service.
+/// With [TariffLimits] unary requests wait for quota and streams over limit are rejected with `RESOURCE_EXHAUSTED`,
+/// so limits of tariff work for [crate::Api] without [crate::ApiPool].
+#[derive(Debug, Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limit: Arc<ServerRateLimit>,
+    tariff: Option<Arc<TariffLimits>>,
+}
+
+impl<S> RateLimitService<S> {
+    pub fn new(inner: S) -> Self {
+        Self::with_limit(inner, Default::default())
+    }
+    /// Share server limits with other services with the same token
+    pub fn with_limit(inner: S, limit: Arc<ServerRateLimit>) -> Self {
+        Self { inner, limit, tariff: None }
+    }
+    /// Apply limits of tariff to requests and streams. Don't set the same limits to [crate::ApiPool] over this service,
+    /// otherwise each stream takes two slots
+    pub fn with_tariff_limits(mut self, limits: impl Into<Arc<TariffLimits>>) -> Self {
+        self.tariff = Some(limits.into());
+        self
+    }
+    pub fn limit(&self) -> &Arc<ServerRateLimit> {
+        &self.limit
+    }
+    pub fn tariff_limits(&self) -> Option<&Arc<TariffLimits>> {
+        self.tariff.as_ref()
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for RateLimitService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    ReqBody: Send + 'static,
+    ResBody: Send + 'static,
+{
+    type Response = http::Response<PermitBody<ResBody>>;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+    fn call(&mut self, req: http::Request<ReqBody>) -> Self::Future {
+        // use service, that is ready, and leave clone instead of it
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let limit = self.limit.clone();
+        let tariff = self.tariff.clone();
+        Box::pin(async move {
+            let method = req.uri().path().trim_start_matches('/').to_owned();
+            let mut permit = None;
+            if let Some(tariff) = &tariff {
+                tariff.acquire_unary(&method).await;
+                match tariff.acquire_stream(&method) {
+                    Ok(p) => permit = p,
+                    Err(_) => {
+                        let status = tonic::Status::resource_exhausted(format!("limit of streams {method} is exceeded"));
+                        return Ok(status.into_http());
+                    }
+                }
+            }
+            limit.wait(&method).await;
+            let res = inner.call(req).await?;
+            limit.update(&method, res.headers());
+            Ok(res.map(|body| PermitBody::new(body, permit)))
+        })
+    }
+}
+
+/// Body of response, that holds slot of stream while stream is alive. Rejected stream has no body
+#[derive(Debug)]
+pub struct PermitBody<B> {
+    body: Option<B>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl<B> PermitBody<B> {
+    pub fn new(body: B, permit: Option<OwnedSemaphorePermit>) -> Self {
+        Self { body: Some(body), _permit: permit }
+    }
+}
+
+impl<B> Default for PermitBody<B> {
+    fn default() -> Self {
+        Self { body: None, _permit: None }
+    }
+}
+
+impl<B: Body + Unpin> Body for PermitBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+    fn poll_frame(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
+        match &mut self.body {
+            Some(body) => Pin::new(body).poll_frame(cx),
+            None => Poll::Ready(None),
+        }
+    }
+    fn is_end_stream(&self) -> bool {
+        self.body.as_ref().is_none_or(|b| b.is_end_stream())
+    }
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.body.as_ref().map(|b| b.size_hint()).unwrap_or_else(|| http_body::SizeHint::with_exact(0))
+    }
+}
+
+/// Limits of unary requests and streams. Methods and streams, absent in tariff, are not limited.
+/// Share one instance (in [Arc]) between all connections with the same token.
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+/// #    let token = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
+///     let api = Api::create_invest_service(token.clone()).unwrap();
+///     let limits = yatis::limits::TariffLimits::load(&api).await.unwrap();
+///     println!("{limits:?}");
+///     // all requests and streams of this api are limited
+///     let api = ApiBuilder::new(token).with_tariff_limits(limits).build().unwrap();
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct TariffLimits {
+    unary: RateLimiter,
+    streams: HashMap<String, Arc<Semaphore>>,
+}
+
+impl TariffLimits {
+    /// Load limits of user's tariff from API
+    pub async fn load<A: OwnedSender<GetUserTariffRequest, GetUserTariffResponse>>(api: &A) -> Result<Self, tonic::Status> {
+        let tariff = api.send(GetUserTariffRequest {}).await?;
+        Ok(tariff.into())
+    }
+    /// Requests per minute for method, if limited
+    pub fn unary_limit(&self, method: &str) -> Option<usize> {
+        self.unary.limit(method)
+    }
+    /// Free slots for opening of stream, if limited
+    pub fn stream_limit(&self, stream: &str) -> Option<usize> {
+        self.streams.get(stream).map(|s| s.available_permits())
+    }
+    /// Waits until unary request of method is allowed
+    pub async fn acquire_unary(&self, method: &str) {
+        self.unary.acquire(method).await
+    }
+    /// Take slot for opening stream. Slot is released on drop of permit. Returns error if all slots are used
+    pub fn acquire_stream(&self, stream: &str) -> Result<Option<OwnedSemaphorePermit>, TryAcquireError> {
+        let Some(semaphore) = self.streams.get(stream) else {
+            return Ok(None);
+        };
+        semaphore.clone().try_acquire_owned().map(Some)
+    }
+}
+
+impl From<GetUserTariffResponse> for TariffLimits {
+    fn from(value: GetUserTariffResponse) -> Self {
+        let GetUserTariffResponse { unary_limits, stream_limits, .. } = value;
+        let mut streams = HashMap::new();
+        for limit in stream_limits {
+            let semaphore = Arc::new(Semaphore::new(limit.limit.saturating_sub(limit.open).max(0) as usize));
+            for stream in limit.streams {
+                streams.insert(stream, semaphore.clone());
+            }
+        }
+        Self { unary: unary_limits.into(), streams }
+    }
+}
+
+/// Sink, that holds slot of stream while stream is alive
+pub struct PermitSink<S> {
+    sink: S,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl<S> PermitSink<S> {
+    pub fn new(sink: S, permit: Option<OwnedSemaphorePermit>) -> Self {
+        Self { sink, _permit: permit }
+    }
+}
+
+impl<T, S: futures::Sink<T> + Unpin> futures::Sink<T> for PermitSink<S> {
+    type Error = S::Error;
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sink).poll_ready(cx)
+    }
+    fn start_send(mut self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        Pin::new(&mut self.sink).start_send(item)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sink).poll_flush(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.sink).poll_close(cx)
+    }
+}
+
+#[test]
+fn test_token_bucket() {
+    let bucket = TokenBucket::new(60);
+    for _ in 0..60 {
+        assert!(bucket.reserve().is_zero());
+    }
+    let wait = bucket.reserve();
+    assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1));
+    let wait = bucket.reserve();
+    assert!(wait > Duration::from_millis(1900) && wait <= Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn test_zero_limit() {
+    let limiter = RateLimiter::new()
+        .with_service_limit(service::USERS, 0)
+        .with_method_limit(grpc_method("UsersServiceClient", "get_info"), 0)
+        .with_group_limit([grpc_method("UsersServiceClient", "get_accounts")], 0);
+    assert_eq!(limiter.limit(&grpc_method("UsersServiceClient", "get_info")), None);
+    assert_eq!(limiter.limit(&grpc_method("UsersServiceClient", "get_accounts")), None);
+    limiter.acquire(&grpc_method("UsersServiceClient", "get_info")).await;
+}
+
+#[test]
+fn test_server_limit() {
+    let limit = ServerRateLimit::default();
+    let mut headers = http::HeaderMap::new();
+    headers.insert("x-ratelimit-limit", "100, 100;w=60".parse().unwrap());
+    headers.insert("x-ratelimit-remaining", "0".parse().unwrap());
+    headers.insert("x-ratelimit-reset", "30".parse().unwrap());
+    limit.update("method", &headers);
+    let state = limit.state("method").unwrap();
+    assert_eq!(state.limit, Some(100));
+    assert_eq!(state.remaining, 0);
+    assert!(state.reset > Instant::now() + Duration::from_secs(29));
+    assert!(limit.state("other").is_none());
+}
+
+#[tokio::test]
+async fn test_server_limit_reserve() {
+    let limit = ServerRateLimit::default();
+    let mut headers = http::HeaderMap::new();
+    headers.insert("x-ratelimit-remaining", "1".parse().unwrap());
+    headers.insert("x-ratelimit-reset", "30".parse().unwrap());
+    limit.update("method", &headers);
+    limit.wait("method").await;
+    assert_eq!(limit.state("method").unwrap().remaining, 0);
+    assert!(tokio::time::timeout(Duration::from_millis(10), limit.wait("method")).await.is_err());
+}
+
+#[test]
+fn test_grpc_method() {
+    assert_eq!(grpc_method("UsersServiceClient", "get_info"), "tinkoff.public.invest.api.contract.v1.UsersService/GetInfo");
+    assert_eq!(
+        grpc_method("SandboxServiceClient", "get_sandbox_operations_by_cursor"),
+        "tinkoff.public.invest.api.contract.v1.SandboxService/GetSandboxOperationsByCursor"
+    );
+}
+
+#[cfg(feature = "mock")]
+#[tokio::test]
+async fn test_tariff_streams() {
+    use crate::t_types::{PortfolioStreamRequest, StreamLimit};
+    let mock = crate::mock::MockServer::start().await.unwrap();
+    let name = <crate::Api as StartStream<PortfolioStreamRequest, StreamResponse>>::stream_name();
+    let limits = TariffLimits::from(GetUserTariffResponse {
+        stream_limits: vec![StreamLimit { limit: 1, streams: vec![name.into()], open: 0 }],
+        ..Default::default()
+    });
+    let api = mock.builder().with_tariff_limits(limits).build().unwrap();
+    let (s, _r) = futures::channel::mpsc::channel::<StreamResponse>(10);
+    let handle = api.start_stream(PortfolioStreamRequest::default(), s.clone()).await.unwrap();
+    let err = api.start_stream(PortfolioStreamRequest::default(), s.clone()).await.err().unwrap();
+    assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+    handle.stop();
+    handle.join().await.unwrap();
+    api.start_stream(PortfolioStreamRequest::default(), s).await.unwrap();
+}
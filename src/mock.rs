@@ -0,0 +1,352 @@
+//! In-process mock of api for offline tests. Unary methods are answered by scripted handlers,
+//! responses of streams are pushed by test into all opened streams of the same type.
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use futures::StreamExt;
+use tokio::sync::{broadcast, oneshot};
+use tonic::codec::CompressionEncoding::Gzip as GZIP;
+use tonic::codegen::BoxStream;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::requestor::OwnedSender;
+use crate::stream::StartStream;
+use crate::t_types::*;
+use crate::{Api, ApiBuilder, StreamResponse};
+
+use crate::t_types::instruments_service_server::{InstrumentsService, InstrumentsServiceServer};
+use crate::t_types::market_data_service_server::{MarketDataService, MarketDataServiceServer};
+use crate::t_types::market_data_stream_service_server::{MarketDataStreamService, MarketDataStreamServiceServer};
+use crate::t_types::operations_service_server::{OperationsService, OperationsServiceServer};
+use crate::t_types::operations_stream_service_server::{OperationsStreamService, OperationsStreamServiceServer};
+use crate::t_types::orders_service_server::{OrdersService, OrdersServiceServer};
+use crate::t_types::orders_stream_service_server::{OrdersStreamService, OrdersStreamServiceServer};
+use crate::t_types::signal_service_server::{SignalService, SignalServiceServer};
+use crate::t_types::stop_orders_service_server::{StopOrdersService, StopOrdersServiceServer};
+use crate::t_types::users_service_server::{UsersService, UsersServiceServer};
+
+/// Capacity of stream buffer, older responses are dropped for slow streams
+const STREAM_CAPACITY: usize = 1024;
+
+type Handler = Arc<dyn Fn(Box<dyn Any + Send>) -> Result<Box<dyn Any + Send>, Box<Status>> + Send + Sync>;
+
+#[derive(Default)]
+struct MockState {
+    handlers: Mutex<HashMap<&'static str, Handler>>,
+    requests: Mutex<Vec<(&'static str, Box<dyn Any + Send>)>>,
+    /// response type -> `broadcast::Sender` of this type
+    streams: Mutex<HashMap<TypeId, Box<dyn Any + Send>>>,
+}
+
+impl MockState {
+    fn record<Req: Send + 'static>(&self, method: &'static str, req: Req) {
+        self.requests.lock().unwrap().push((method, Box::new(req)));
+    }
+    async fn unary<Req, Res>(&self, req: Req) -> Result<Res, Status>
+    where Api: OwnedSender<Req, Res>, Req: Clone + Send + 'static, Res: Send + 'static {
+        let method = <Api as OwnedSender<Req, Res>>::method_name();
+        self.record(method, req.clone());
+        let handler = self.handlers.lock().unwrap().get(method).cloned();
+        let handler = handler.ok_or_else(|| Status::unimplemented(format!("no mock for {method}")))?;
+        let res = handler(Box::new(req)).map_err(|e| *e)?;
+        res.downcast::<Res>().map(|res| *res).map_err(|_| Status::internal(format!("wrong type of mock response for {method}")))
+    }
+    fn sender<Res: Clone + Send + 'static>(&self) -> broadcast::Sender<Res> {
+        let mut streams = self.streams.lock().unwrap();
+        let sender = streams.entry(TypeId::of::<Res>()).or_insert_with(|| Box::new(broadcast::channel::<Res>(STREAM_CAPACITY).0));
+        sender.downcast_ref::<broadcast::Sender<Res>>().expect("sender of stream type").clone()
+    }
+    fn subscribe<Res: Clone + Send + 'static>(&self) -> BoxStream<Res> {
+        let receiver = self.sender::<Res>().subscribe();
+        futures::stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(res) => return Some((Ok(res), receiver)),
+                    Err(broadcast::error::RecvError::Lagged(n)) => log::warn!("mock stream lagged, {n} responses skipped"),
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        }).boxed()
+    }
+    async fn stream<Req, Res>(&self, req: Req) -> Result<Response<BoxStream<Res>>, Status>
+    where Api: StartStream<Req, StreamResponse>, Req: Send + 'static, Res: Clone + Send + 'static {
+        self.record(<Api as StartStream<Req, StreamResponse>>::stream_name(), req);
+        Ok(Response::new(self.subscribe()))
+    }
+}
+
+/// Service of mock server, implements all services of api
+#[derive(Clone)]
+struct MockService(Arc<MockState>);
+
+macro_rules! mock_service {
+    ($service:ident { $($res:ty = $method:ident($req:ty),)+ }) => {
+        #[tonic::async_trait]
+        impl $service for MockService {$(
+            async fn $method(&self, request: Request<$req>) -> Result<Response<$res>, Status> {
+                self.0.unary(request.into_inner()).await.map(Response::new)
+            }
+        )+}
+    };
+}
+
+mock_service!(InstrumentsService {
+    BondResponse = bond_by(InstrumentRequest),
+    BondsResponse = bonds(InstrumentsRequest),
+    CreateFavoriteGroupResponse = create_favorite_group(CreateFavoriteGroupRequest),
+    DeleteFavoriteGroupResponse = delete_favorite_group(DeleteFavoriteGroupRequest),
+    CurrenciesResponse = currencies(InstrumentsRequest),
+    CurrencyResponse = currency_by(InstrumentRequest),
+    EditFavoritesResponse = edit_favorites(EditFavoritesRequest),
+    EtfResponse = etf_by(InstrumentRequest),
+    EtfsResponse = etfs(InstrumentsRequest),
+    FindInstrumentResponse = find_instrument(FindInstrumentRequest),
+    FutureResponse = future_by(InstrumentRequest),
+    FuturesResponse = futures(InstrumentsRequest),
+    GetAccruedInterestsResponse = get_accrued_interests(GetAccruedInterestsRequest),
+    AssetResponse = get_asset_by(AssetRequest),
+    GetAssetFundamentalsResponse = get_asset_fundamentals(GetAssetFundamentalsRequest),
+    GetAssetReportsResponse = get_asset_reports(GetAssetReportsRequest),
+    AssetsResponse = get_assets(AssetsRequest),
+    GetBondCouponsResponse = get_bond_coupons(GetBondCouponsRequest),
+    GetBondEventsResponse = get_bond_events(GetBondEventsRequest),
+    Brand = get_brand_by(GetBrandRequest),
+    GetBrandsResponse = get_brands(GetBrandsRequest),
+    GetConsensusForecastsResponse = get_consensus_forecasts(GetConsensusForecastsRequest),
+    GetCountriesResponse = get_countries(GetCountriesRequest),
+    GetDividendsResponse = get_dividends(GetDividendsRequest),
+    GetFavoriteGroupsResponse = get_favorite_groups(GetFavoriteGroupsRequest),
+    GetFavoritesResponse = get_favorites(GetFavoritesRequest),
+    GetForecastResponse = get_forecast_by(GetForecastRequest),
+    GetFuturesMarginResponse = get_futures_margin(GetFuturesMarginRequest),
+    InstrumentResponse = get_instrument_by(InstrumentRequest),
+    RiskRatesResponse = get_risk_rates(RiskRatesRequest),
+    IndicativesResponse = indicatives(IndicativesRequest),
+    OptionResponse = option_by(InstrumentRequest),
+    OptionsResponse = options_by(FilterOptionsRequest),
+    ShareResponse = share_by(InstrumentRequest),
+    SharesResponse = shares(InstrumentsRequest),
+    TradingSchedulesResponse = trading_schedules(TradingSchedulesRequest),
+});
+
+mock_service!(MarketDataService {
+    GetCandlesResponse = get_candles(GetCandlesRequest),
+    GetClosePricesResponse = get_close_prices(GetClosePricesRequest),
+    GetLastPricesResponse = get_last_prices(GetLastPricesRequest),
+    GetLastTradesResponse = get_last_trades(GetLastTradesRequest),
+    GetOrderBookResponse = get_order_book(GetOrderBookRequest),
+    GetTechAnalysisResponse = get_tech_analysis(GetTechAnalysisRequest),
+    GetTradingStatusResponse = get_trading_status(GetTradingStatusRequest),
+    GetTradingStatusesResponse = get_trading_statuses(GetTradingStatusesRequest),
+});
+
+mock_service!(OperationsService {
+    GetDividendsForeignIssuerResponse = get_dividends_foreign_issuer(GetDividendsForeignIssuerRequest),
+    OperationsResponse = get_operations(OperationsRequest),
+    GetOperationsByCursorResponse = get_operations_by_cursor(GetOperationsByCursorRequest),
+    PortfolioResponse = get_portfolio(PortfolioRequest),
+    PositionsResponse = get_positions(PositionsRequest),
+    WithdrawLimitsResponse = get_withdraw_limits(WithdrawLimitsRequest),
+});
+
+mock_service!(OrdersService {
+    CancelOrderResponse = cancel_order(CancelOrderRequest),
+    GetMaxLotsResponse = get_max_lots(GetMaxLotsRequest),
+    GetOrderPriceResponse = get_order_price(GetOrderPriceRequest),
+    OrderState = get_order_state(GetOrderStateRequest),
+    GetOrdersResponse = get_orders(GetOrdersRequest),
+    PostOrderResponse = post_order(PostOrderRequest),
+    PostOrderAsyncResponse = post_order_async(PostOrderAsyncRequest),
+    PostOrderResponse = replace_order(ReplaceOrderRequest),
+});
+
+mock_service!(SignalService {
+    GetSignalsResponse = get_signals(GetSignalsRequest),
+    GetStrategiesResponse = get_strategies(GetStrategiesRequest),
+});
+
+mock_service!(StopOrdersService {
+    CancelStopOrderResponse = cancel_stop_order(CancelStopOrderRequest),
+    GetStopOrdersResponse = get_stop_orders(GetStopOrdersRequest),
+    PostStopOrderResponse = post_stop_order(PostStopOrderRequest),
+});
+
+mock_service!(UsersService {
+    GetAccountsResponse = get_accounts(GetAccountsRequest),
+    GetInfoResponse = get_info(GetInfoRequest),
+    GetMarginAttributesResponse = get_margin_attributes(GetMarginAttributesRequest),
+    GetUserTariffResponse = get_user_tariff(GetUserTariffRequest),
+});
+
+#[tonic::async_trait]
+impl OrdersStreamService for MockService {
+    async fn trades_stream(&self, request: Request<TradesStreamRequest>) -> Result<Response<BoxStream<TradesStreamResponse>>, Status> {
+        self.0.stream(request.into_inner()).await
+    }
+    async fn order_state_stream(&self, request: Request<OrderStateStreamRequest>) -> Result<Response<BoxStream<OrderStateStreamResponse>>, Status> {
+        self.0.stream(request.into_inner()).await
+    }
+}
+
+#[tonic::async_trait]
+impl OperationsStreamService for MockService {
+    async fn portfolio_stream(&self, request: Request<PortfolioStreamRequest>) -> Result<Response<BoxStream<PortfolioStreamResponse>>, Status> {
+        self.0.stream(request.into_inner()).await
+    }
+    async fn positions_stream(&self, request: Request<PositionsStreamRequest>) -> Result<Response<BoxStream<PositionsStreamResponse>>, Status> {
+        self.0.stream(request.into_inner()).await
+    }
+}
+
+#[tonic::async_trait]
+impl MarketDataStreamService for MockService {
+    async fn market_data_stream(&self, request: Request<Streaming<MarketDataRequest>>) -> Result<Response<BoxStream<MarketDataResponse>>, Status> {
+        static NAME: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+        let method = NAME.get_or_init(|| crate::limits::grpc_method("MarketDataStreamServiceClient", "market_data_stream"));
+        let mut requests = request.into_inner();
+        let state = self.0.clone();
+        tokio::spawn(async move {
+            while let Some(Ok(req)) = requests.next().await {
+                state.record(method.as_str(), req);
+            }
+        });
+        Ok(Response::new(self.0.subscribe()))
+    }
+    async fn market_data_server_side_stream(&self, request: Request<MarketDataServerSideStreamRequest>) -> Result<Response<BoxStream<MarketDataResponse>>, Status> {
+        self.0.stream(request.into_inner()).await
+    }
+}
+
+/// In-process grpc server with scripted responses. Server is stopped, when mock is dropped.
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use yatis::mock::MockServer;
+///     use t_types::*;
+///     let mock = MockServer::start().await.unwrap();
+///     mock.respond(GetInfoResponse { tariff: "investor".into(), ..Default::default() });
+///     let api = mock.api();
+///     let info: GetInfoResponse = api.request(GetInfoRequest {}).await.unwrap();
+///     assert_eq!(info.tariff, "investor");
+///     assert_eq!(mock.requests::<GetInfoRequest>().len(), 1);
+/// # }
+/// ```
+pub struct MockServer {
+    state: Arc<MockState>,
+    addr: SocketAddr,
+    shutdown: std::option::Option<oneshot::Sender<()>>,
+}
+
+impl MockServer {
+    /// Start server on random local port
+    pub async fn start() -> std::io::Result<Self> {
+        let incoming = TcpIncoming::bind(([127, 0, 0, 1], 0).into())?;
+        let addr = incoming.local_addr()?;
+        let state = Arc::new(MockState::default());
+        let service = MockService(state.clone());
+        let (shutdown, signal) = oneshot::channel::<()>();
+        let router = tonic::transport::Server::builder()
+            .add_service(InstrumentsServiceServer::new(service.clone()).accept_compressed(GZIP).send_compressed(GZIP))
+            .add_service(MarketDataServiceServer::new(service.clone()).accept_compressed(GZIP).send_compressed(GZIP))
+            .add_service(MarketDataStreamServiceServer::new(service.clone()).accept_compressed(GZIP).send_compressed(GZIP))
+            .add_service(OperationsServiceServer::new(service.clone()).accept_compressed(GZIP).send_compressed(GZIP))
+            .add_service(OperationsStreamServiceServer::new(service.clone()).accept_compressed(GZIP).send_compressed(GZIP))
+            .add_service(OrdersServiceServer::new(service.clone()).accept_compressed(GZIP).send_compressed(GZIP))
+            .add_service(OrdersStreamServiceServer::new(service.clone()).accept_compressed(GZIP).send_compressed(GZIP))
+            .add_service(SignalServiceServer::new(service.clone()).accept_compressed(GZIP).send_compressed(GZIP))
+            .add_service(StopOrdersServiceServer::new(service.clone()).accept_compressed(GZIP).send_compressed(GZIP))
+            .add_service(UsersServiceServer::new(service).accept_compressed(GZIP).send_compressed(GZIP));
+        tokio::spawn(async move {
+            if let Err(e) = router.serve_with_incoming_shutdown(incoming, async { let _ = signal.await; }).await {
+                log::error!("mock server failed: {e:?}");
+            }
+        });
+        Ok(Self { state, addr, shutdown: Some(shutdown) })
+    }
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+    /// Builder of api, connected to mock server
+    pub fn builder(&self) -> ApiBuilder {
+        ApiBuilder::new("mock-token").with_endpoint(format!("http://{}", self.addr))
+    }
+    /// Api, connected to mock server
+    pub fn api(&self) -> Api {
+        self.builder().build().expect("valid endpoint of mock server")
+    }
+    /// Answer requests of method by handler
+    pub fn on<Req, Res>(&self, handler: impl Fn(Req) -> Result<Res, Status> + Send + Sync + 'static)
+    where Api: OwnedSender<Req, Res>, Req: Send + 'static, Res: Send + 'static {
+        let handler: Handler = Arc::new(move |req| {
+            let req = *req.downcast::<Req>().expect("request type of method");
+            handler(req).map(|res| Box::new(res) as Box<dyn Any + Send>).map_err(Box::new)
+        });
+        self.state.handlers.lock().unwrap().insert(<Api as OwnedSender<Req, Res>>::method_name(), handler);
+    }
+    /// Answer all requests of method with the same response. Method is detected by type of response,
+    /// for responses of several methods (e.g. [PostOrderResponse]) use [MockServer::on]
+    pub fn respond<Req, Res>(&self, res: Res)
+    where Api: OwnedSender<Req, Res>, Req: Send + 'static, Res: Clone + Send + Sync + 'static {
+        let handler: Handler = Arc::new(move |_| Ok(Box::new(res.clone())));
+        self.state.handlers.lock().unwrap().insert(<Api as OwnedSender<Req, Res>>::method_name(), handler);
+    }
+    /// Send response to all opened streams with this type of responses. Returns count of streams
+    pub fn push<Res: Clone + Send + 'static>(&self, res: Res) -> usize {
+        self.state.sender::<Res>().send(res).unwrap_or_default()
+    }
+    /// Count of opened streams with this type of responses
+    pub fn streams<Res: Clone + Send + 'static>(&self) -> usize {
+        self.state.sender::<Res>().receiver_count()
+    }
+    /// All received requests of this type, including stream requests
+    pub fn requests<Req: Clone + 'static>(&self) -> Vec<Req> {
+        self.state.requests.lock().unwrap().iter().filter_map(|(_, req)| req.downcast_ref::<Req>()).cloned().collect()
+    }
+    /// Names of called methods in order of calls
+    pub fn calls(&self) -> Vec<&'static str> {
+        self.state.requests.lock().unwrap().iter().map(|(method, _)| *method).collect()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+#[tokio::test]
+#[allow(clippy::result_large_err)]
+async fn test_mock() {
+    use crate::Requestor;
+    let mock = MockServer::start().await.unwrap();
+    let api = mock.api();
+    mock.on(|req: GetOrdersRequest| Ok(GetOrdersResponse {
+        orders: vec![OrderState { order_id: req.account_id, ..Default::default() }],
+    }));
+    let res: GetOrdersResponse = api.request(GetOrdersRequest { account_id: "acc".into() }).await.unwrap();
+    assert_eq!(res.orders[0].order_id, "acc");
+    mock.on(|_: GetAccountsRequest| Err(Status::permission_denied("no accounts")));
+    let err = Requestor::<GetInfoRequest, GetInfoResponse>::request(&api, GetInfoRequest {}).await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::Unimplemented);
+    let err = Requestor::<GetAccountsRequest, GetAccountsResponse>::request(&api, GetAccountsRequest::default()).await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    assert_eq!(mock.calls().len(), 3);
+
+    let (s, mut r) = futures::channel::mpsc::channel::<StreamResponse>(10);
+    let _handle = api.start_stream(PortfolioStreamRequest::default(), s).await.unwrap();
+    while mock.streams::<PortfolioStreamResponse>() == 0 {
+        tokio::task::yield_now().await;
+    }
+    let portfolio = PortfolioResponse { account_id: "acc".into(), ..Default::default() };
+    mock.push(PortfolioStreamResponse { payload: Some(portfolio_stream_response::Payload::Portfolio(portfolio)) });
+    let Some(StreamResponse::PortfolioResponse(p)) = r.next().await else { panic!("portfolio expected") };
+    assert_eq!(p.account_id, "acc");
+    assert_eq!(mock.requests::<PortfolioStreamRequest>().len(), 1);
+}
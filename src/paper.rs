@@ -0,0 +1,1022 @@
+//! Local paper trading: accounts, money and positions are kept in memory, orders are matched against supplied prices.
+//! Unsupported requests return `UNIMPLEMENTED`.
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use futures::SinkExt;
+use log::warn;
+use tokio::sync::{broadcast, watch};
+
+use crate::error::ErrorCode;
+use crate::requestor::{AnyRequestor, OwnedSender};
+use crate::stream::{AnyStream, StartStream, StreamControl, StreamEvent, StreamHandle, StreamMonitor};
+use crate::stream_response::OrderStateResponse;
+use crate::t_types::*;
+use crate::{Api, StreamResponse};
+
+/// Capacity of events buffer, older events are dropped for slow streams
+const EVENTS_CAPACITY: usize = 1024;
+
+
+fn money(value: Quotation, currency: &str) -> MoneyValue {
+    MoneyValue { currency: currency.to_owned(), units: value.units, nano: value.nano }
+}
+
+fn instrument_key(figi: &str, instrument_id: &str) -> String {
+    if instrument_id.is_empty() { figi.to_owned() } else { instrument_id.to_owned() }
+}
+
+/// Is instrument of subscription the same as instrument of market data
+fn subscribed(figi: &str, instrument_id: &str, data_figi: &str, data_uid: &str) -> bool {
+    let id = instrument_key(figi, instrument_id);
+    id == data_figi || id == data_uid
+}
+
+/// Price for execution of orders
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FillPrice {
+    /// Market orders are executed at current price immediately (close of candle in backtest)
+    #[default]
+    Current,
+    /// Market orders are executed at next received price (open of next candle in backtest)
+    Next,
+}
+
+/// Model of orders execution
+/// # Examples:
+/// ```rust
+///     use yatis::paper::{FillModel, FillPrice, PaperApi};
+///     let model = FillModel::new(FillPrice::Next).with_slippage(0.001).with_commission(0.0005);
+///     let api = PaperApi::new().with_account("paper", 10000.0).with_fill_model(model);
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FillModel {
+    price: FillPrice,
+    slippage: Quotation,
+    commission: Quotation,
+}
+
+impl FillModel {
+    pub fn new(price: FillPrice) -> Self {
+        Self { price, ..Default::default() }
+    }
+    /// Part of price, that market orders lose on execution: buy is more expensive, sell is cheaper
+    pub fn with_slippage(self, slippage: f64) -> Self {
+        Self { slippage: slippage.into(), ..self }
+    }
+    /// Part of deal amount, paid as commission
+    pub fn with_commission(self, commission: f64) -> Self {
+        Self { commission: commission.into(), ..self }
+    }
+    fn price(&self, order: &PaperOrder, price: Quotation) -> Quotation {
+        let one = Quotation { units: 1, nano: 0 };
+        match (order.order_type, order.direction) {
+            (OrderType::Limit, _) => price,
+            (_, OrderDirection::Buy) => price * (one + self.slippage),
+            _ => price * (one - self.slippage),
+        }
+    }
+}
+
+/// Executed deal of paper api
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaperTrade {
+    pub time: prost_types::Timestamp,
+    pub account_id: String,
+    pub order_id: String,
+    pub instrument_id: String,
+    pub direction: OrderDirection,
+    /// count of securities (not lots)
+    pub quantity: i64,
+    /// price of one security
+    pub price: Quotation,
+    pub commission: Quotation,
+}
+
+/// Event of paper account, sent to streams
+#[derive(Debug, Clone)]
+enum PaperEvent {
+    OrderState(OrderStateResponse),
+    Trades(OrderTrades),
+    Position(PositionData),
+    Portfolio(PortfolioResponse),
+    Market(MarketDataResponse),
+}
+
+impl PaperEvent {
+    fn account(&self) -> &str {
+        match self {
+            Self::OrderState(x) => &x.account_id,
+            Self::Trades(x) => &x.account_id,
+            Self::Position(x) => &x.account_id,
+            Self::Portfolio(x) => &x.account_id,
+            Self::Market(_) => "",
+        }
+    }
+    /// Event is for accounts of request (empty means all)
+    fn for_accounts(&self, accounts: &[String]) -> bool {
+        accounts.is_empty() || accounts.iter().any(|a| a == self.account())
+    }
+    /// Market data is subscribed by request
+    fn for_market(&self, req: &MarketDataServerSideStreamRequest) -> bool {
+        use market_data_response::Payload;
+        let Self::Market(MarketDataResponse { payload: Some(payload) }) = self else { return false };
+        match payload {
+            Payload::Candle(c) => req.subscribe_candles_request.iter().flat_map(|r| &r.instruments)
+                .any(|i| subscribed(&i.figi, &i.instrument_id, &c.figi, &c.instrument_uid)
+                    && (i.interval == 0 || i.interval == c.interval)),
+            Payload::LastPrice(p) => req.subscribe_last_price_request.iter().flat_map(|r| &r.instruments)
+                .any(|i| subscribed(&i.figi, &i.instrument_id, &p.figi, &p.instrument_uid)),
+            Payload::Trade(t) => req.subscribe_trades_request.iter().flat_map(|r| &r.instruments)
+                .any(|i| subscribed(&i.figi, &i.instrument_id, &t.figi, &t.instrument_uid)),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Holding {
+    /// count of securities (not lots)
+    balance: i64,
+    average_price: Quotation,
+}
+
+#[derive(Debug, Default)]
+struct PaperAccount {
+    money: Quotation,
+    holdings: BTreeMap<String, Holding>,
+}
+
+#[derive(Debug, Clone)]
+struct PaperOrder {
+    order_id: String,
+    request_id: String,
+    account_id: String,
+    instrument_id: String,
+    direction: OrderDirection,
+    order_type: OrderType,
+    lots: i64,
+    limit: std::option::Option<Quotation>,
+    status: OrderExecutionReportStatus,
+    created_at: prost_types::Timestamp,
+    trades: Vec<OrderTrade>,
+    commission: Quotation,
+}
+
+impl PaperOrder {
+    fn is_active(&self) -> bool {
+        self.status == OrderExecutionReportStatus::ExecutionReportStatusNew
+    }
+    fn executed_price(&self) -> std::option::Option<Quotation> {
+        self.trades.first().and_then(|t| t.price)
+    }
+}
+
+#[derive(Debug)]
+struct PaperState {
+    currency: String,
+    accounts: BTreeMap<String, PaperAccount>,
+    /// instrument -> lot size
+    lots: HashMap<String, i64>,
+    prices: HashMap<String, Quotation>,
+    orders: BTreeMap<String, PaperOrder>,
+    next_id: u64,
+    fill: FillModel,
+    /// simulated time, system time is used if absent
+    time: std::option::Option<prost_types::Timestamp>,
+    trades: Vec<PaperTrade>,
+}
+
+impl PaperState {
+    fn now(&self) -> prost_types::Timestamp {
+        self.time.unwrap_or_else(|| SystemTime::now().into())
+    }
+    fn lot(&self, instrument_id: &str) -> i64 {
+        self.lots.get(instrument_id).copied().unwrap_or(1)
+    }
+    fn account(&mut self, account_id: &str) -> Result<&mut PaperAccount, Box<tonic::Status>> {
+        self.accounts.get_mut(account_id).ok_or_else(|| Box::new(tonic::Status::permission_denied(format!("no account {account_id}"))))
+    }
+    fn order(&self, account_id: &str, order_id: &str) -> Result<&PaperOrder, Box<tonic::Status>> {
+        self.orders.get(order_id).filter(|o| o.account_id == account_id)
+            .ok_or_else(|| Box::new(tonic::Status::not_found(format!("no order {order_id}"))))
+    }
+    /// Active orders of account, except order `except`
+    fn active_orders<'a>(&'a self, account_id: &'a str, except: &'a str) -> impl Iterator<Item = &'a PaperOrder> + 'a {
+        self.orders.values().filter(move |o| o.is_active() && o.account_id == account_id && o.order_id != except)
+    }
+    /// Money, reserved by active buy orders at limit (or current) price with commission
+    fn reserved_money(&self, account_id: &str, except: &str) -> Quotation {
+        let one = Quotation { units: 1, nano: 0 };
+        self.active_orders(account_id, except).filter(|o| o.direction == OrderDirection::Buy).filter_map(|o| {
+            let price = o.limit.or_else(|| self.prices.get(&o.instrument_id).copied())?;
+            Some(price * (o.lots * self.lot(&o.instrument_id)) * (one + self.fill.commission))
+        }).sum()
+    }
+    /// Count of securities, reserved by active sell orders
+    fn reserved_assets(&self, account_id: &str, instrument_id: &str, except: &str) -> i64 {
+        self.active_orders(account_id, except).filter(|o| o.direction == OrderDirection::Sell && o.instrument_id == instrument_id)
+            .map(|o| o.lots * self.lot(&o.instrument_id)).sum()
+    }
+    /// Check money and assets for order at price, excluding money and assets reserved by other active orders
+    fn check(&mut self, order: &PaperOrder, price: Quotation) -> Result<(), Box<tonic::Status>> {
+        let count = order.lots * self.lot(&order.instrument_id);
+        let commission = self.fill.commission;
+        let reserved_money = self.reserved_money(&order.account_id, &order.order_id);
+        let reserved_assets = self.reserved_assets(&order.account_id, &order.instrument_id, &order.order_id);
+        let account = self.account(&order.account_id)?;
+        match order.direction {
+            OrderDirection::Buy if account.money - reserved_money < price * count + price * count * commission => Err(Box::new(ErrorCode::NotEnoughBalance.status("not enough balance"))),
+            OrderDirection::Sell if account.holdings.get(&order.instrument_id).map_or(0, |h| h.balance) - reserved_assets < count => {
+                Err(Box::new(ErrorCode::NotEnoughAssets.status("not enough assets")))
+            }
+            _ => Ok(()),
+        }
+    }
+    /// Price of execution, if order can be executed now
+    fn execution_price(&self, order: &PaperOrder) -> std::option::Option<Quotation> {
+        let price = *self.prices.get(&order.instrument_id)?;
+        match (order.order_type, order.limit, order.direction) {
+            (OrderType::Limit, Some(limit), OrderDirection::Buy) if price > limit => None,
+            (OrderType::Limit, Some(limit), OrderDirection::Sell) if price < limit => None,
+            _ => Some(self.fill.price(order, price)),
+        }
+    }
+    /// Execute order, if possible. Order without money or assets is rejected
+    fn execute(&mut self, order_id: &str, events: &mut Vec<PaperEvent>) {
+        let Some(order) = self.orders.get(order_id).filter(|o| o.is_active()).cloned() else { return };
+        let Some(price) = self.execution_price(&order) else { return };
+        if let Err(e) = self.check(&order, price) {
+            warn!("paper order {order_id} is rejected: {e:?}");
+            self.set_status(order_id, OrderExecutionReportStatus::ExecutionReportStatusRejected, events);
+            return;
+        }
+        let count = order.lots * self.lot(&order.instrument_id);
+        let commission = price * count * self.fill.commission;
+        let time = self.now();
+        let Ok(account) = self.account(&order.account_id) else { return };
+        account.money -= commission;
+        let holding = account.holdings.entry(order.instrument_id.clone()).or_default();
+        match order.direction {
+            OrderDirection::Buy => {
+                holding.average_price = (holding.average_price * holding.balance + price * count) / (holding.balance + count);
+                holding.balance += count;
+                account.money -= price * count;
+            }
+            _ => {
+                holding.balance -= count;
+                account.money += price * count;
+            }
+        }
+        if holding.balance == 0 {
+            account.holdings.remove(&order.instrument_id);
+        }
+        let trade = OrderTrade { date_time: Some(time), price: Some(price), quantity: count, trade_id: format!("paper-trade-{}", self.next_id) };
+        self.next_id += 1;
+        self.trades.push(PaperTrade {
+            time,
+            account_id: order.account_id.clone(),
+            order_id: order.order_id.clone(),
+            instrument_id: order.instrument_id.clone(),
+            direction: order.direction,
+            quantity: count,
+            price,
+            commission,
+        });
+        let Some(order) = self.orders.get_mut(order_id) else { return };
+        order.trades.push(trade.clone());
+        order.commission = commission;
+        events.push(PaperEvent::Trades(OrderTrades {
+            order_id: order.order_id.clone(),
+            created_at: Some(order.created_at),
+            direction: order.direction as i32,
+            figi: order.instrument_id.clone(),
+            trades: vec![trade],
+            account_id: order.account_id.clone(),
+            instrument_uid: order.instrument_id.clone(),
+        }));
+        let account_id = order.account_id.clone();
+        self.set_status(order_id, OrderExecutionReportStatus::ExecutionReportStatusFill, events);
+        if let Some(positions) = self.positions(&account_id) {
+            events.push(PaperEvent::Position(PositionData {
+                account_id: account_id.clone(),
+                money: positions.money.into_iter().zip(positions.blocked).map(|(m, b)| PositionsMoney { available_value: Some(m), blocked_value: Some(b) }).collect(),
+                securities: positions.securities,
+                date: Some(time),
+                ..Default::default()
+            }));
+        }
+        events.extend(self.portfolio(&account_id).map(PaperEvent::Portfolio));
+    }
+    fn set_status(&mut self, order_id: &str, status: OrderExecutionReportStatus, events: &mut Vec<PaperEvent>) {
+        if let Some(order) = self.orders.get_mut(order_id) {
+            order.status = status;
+            let order = order.clone();
+            events.push(PaperEvent::OrderState(self.order_state_response(&order)));
+        }
+    }
+    fn order_state(&self, order: &PaperOrder) -> OrderState {
+        let lots_executed = if order.trades.is_empty() { 0 } else { order.lots };
+        let count = order.lots * self.lot(&order.instrument_id);
+        let executed = order.executed_price();
+        OrderState {
+            order_id: order.order_id.clone(),
+            execution_report_status: order.status as i32,
+            lots_requested: order.lots,
+            lots_executed,
+            initial_order_price: order.limit.map(|p| money(p * count, &self.currency)),
+            executed_order_price: executed.map(|p| money(p, &self.currency)),
+            total_order_amount: executed.map(|p| money(p * count, &self.currency)),
+            figi: order.instrument_id.clone(),
+            direction: order.direction as i32,
+            initial_security_price: order.limit.map(|p| money(p, &self.currency)),
+            executed_commission: Some(money(order.commission, &self.currency)),
+            stages: order.trades.iter().map(|t| OrderStage {
+                price: t.price.map(|p| money(p, &self.currency)),
+                quantity: t.quantity,
+                trade_id: t.trade_id.clone(),
+                execution_time: t.date_time,
+            }).collect(),
+            currency: self.currency.clone(),
+            order_type: order.order_type as i32,
+            order_date: Some(order.created_at),
+            instrument_uid: order.instrument_id.clone(),
+            order_request_id: order.request_id.clone(),
+            ..Default::default()
+        }
+    }
+    fn order_state_response(&self, order: &PaperOrder) -> OrderStateResponse {
+        let lots_executed = if order.trades.is_empty() { 0 } else { order.lots };
+        let lots_cancelled = if order.status == OrderExecutionReportStatus::ExecutionReportStatusCancelled { order.lots } else { 0 };
+        let state = self.order_state(order);
+        OrderStateResponse {
+            order_id: state.order_id,
+            order_request_id: Some(state.order_request_id).filter(|id| !id.is_empty()),
+            created_at: state.order_date,
+            execution_report_status: state.execution_report_status,
+            lot_size: self.lot(&order.instrument_id) as i32,
+            direction: state.direction,
+            order_type: state.order_type,
+            account_id: order.account_id.clone(),
+            initial_order_price: state.initial_order_price,
+            order_price: state.initial_security_price,
+            amount: state.total_order_amount,
+            executed_order_price: state.executed_order_price,
+            currency: state.currency,
+            lots_requested: order.lots,
+            lots_executed,
+            lots_left: if order.is_active() { order.lots } else { 0 },
+            lots_cancelled,
+            trades: order.trades.clone(),
+            completion_time: (!order.is_active()).then(|| self.now()),
+            instrument_uid: state.instrument_uid,
+            ..Default::default()
+        }
+    }
+    /// Positions of account, reserved by active orders are blocked
+    fn positions(&self, account_id: &str) -> std::option::Option<PositionsResponse> {
+        let account = self.accounts.get(account_id)?;
+        let reserved = self.reserved_money(account_id, "");
+        Some(PositionsResponse {
+            money: vec![money(account.money - reserved, &self.currency)],
+            blocked: vec![money(reserved, &self.currency)],
+            securities: account.holdings.iter().map(|(instrument_id, h)| {
+                let blocked = self.reserved_assets(account_id, instrument_id, "");
+                PositionsSecurities {
+                    figi: instrument_id.clone(),
+                    blocked,
+                    balance: h.balance - blocked,
+                    instrument_uid: instrument_id.clone(),
+                    ..Default::default()
+                }
+            }).collect(),
+            account_id: account_id.to_owned(),
+            ..Default::default()
+        })
+    }
+    fn portfolio(&self, account_id: &str) -> std::option::Option<PortfolioResponse> {
+        let account = self.accounts.get(account_id)?;
+        let positions: Vec<_> = account.holdings.iter().map(|(instrument_id, h)| {
+            let price = self.prices.get(instrument_id).copied().unwrap_or(h.average_price);
+            PortfolioPosition {
+                figi: instrument_id.clone(),
+                quantity: Some(Quotation { units: h.balance, nano: 0 }),
+                average_position_price: Some(money(h.average_price, &self.currency)),
+                expected_yield: Some((price - h.average_price) * h.balance),
+                current_price: Some(money(price, &self.currency)),
+                instrument_uid: instrument_id.clone(),
+                ..Default::default()
+            }
+        }).collect();
+        let shares: Quotation = account.holdings.iter()
+            .map(|(instrument_id, h)| self.prices.get(instrument_id).copied().unwrap_or(h.average_price) * h.balance).sum();
+        let expected_yield = positions.iter().filter_map(|p| p.expected_yield).sum();
+        Some(PortfolioResponse {
+            total_amount_shares: Some(money(shares, &self.currency)),
+            total_amount_currencies: Some(money(account.money, &self.currency)),
+            total_amount_portfolio: Some(money(shares + account.money, &self.currency)),
+            expected_yield: Some(expected_yield),
+            positions,
+            account_id: account_id.to_owned(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Offline implementation of api for paper trading. Orders are executed by prices from [PaperApi::set_price]
+/// or from market data, passed to [PaperApi::process]. Money and securities of active orders are reserved
+/// and reported as blocked in positions. Type of instruments is unknown, so `instrument_type` is empty.
+/// Clones share the same state.
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use yatis::paper::PaperApi;
+///     use t_types::*;
+///     let api = PaperApi::new().with_account("paper", 1000.0).with_instrument("TCS80A107UL4", 1);
+///     api.set_price("TCS80A107UL4", 100.0.into());
+///     let res: PostOrderResponse = api.request(PostOrderRequest {
+///         instrument_id: "TCS80A107UL4".into(),
+///         account_id: "paper".into(),
+///         quantity: 2,
+///         direction: OrderDirection::Buy.into(),
+///         order_type: OrderType::Market.into(),
+///         ..Default::default()
+///     }).await.unwrap();
+///     assert_eq!(res.lots_executed, 2);
+///     let portfolio: PortfolioResponse = api.request(PortfolioRequest { account_id: "paper".into(), currency: None }).await.unwrap();
+///     println!("{portfolio:?}");
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct PaperApi {
+    state: Arc<Mutex<PaperState>>,
+    events: broadcast::Sender<PaperEvent>,
+}
+
+impl Default for PaperApi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PaperApi {
+    /// Paper api without accounts, with `rub` currency
+    pub fn new() -> Self {
+        let state = PaperState {
+            currency: "rub".into(),
+            accounts: BTreeMap::new(),
+            lots: HashMap::new(),
+            prices: HashMap::new(),
+            orders: BTreeMap::new(),
+            next_id: 1,
+            fill: FillModel::default(),
+            time: None,
+            trades: Vec::new(),
+        };
+        Self { state: Arc::new(Mutex::new(state)), events: broadcast::channel(EVENTS_CAPACITY).0 }
+    }
+    /// Currency of money and prices
+    pub fn with_currency(self, currency: impl ToString) -> Self {
+        self.state.lock().unwrap().currency = currency.to_string();
+        self
+    }
+    /// Add account with money
+    pub fn with_account(self, account_id: impl ToString, money: impl Into<Quotation>) -> Self {
+        let account = PaperAccount { money: money.into(), ..Default::default() };
+        self.state.lock().unwrap().accounts.insert(account_id.to_string(), account);
+        self
+    }
+    /// Lot size of instrument, default is 1
+    pub fn with_instrument(self, instrument_id: impl ToString, lot: i64) -> Self {
+        self.state.lock().unwrap().lots.insert(instrument_id.to_string(), lot.max(1));
+        self
+    }
+    /// Model of orders execution, by default orders are executed at current price without slippage and commission
+    pub fn with_fill_model(self, fill: FillModel) -> Self {
+        self.state.lock().unwrap().fill = fill;
+        self
+    }
+    /// Set simulated time, used for orders and deals. By default system time is used
+    pub fn set_time(&self, time: prost_types::Timestamp) {
+        self.state.lock().unwrap().time = Some(time);
+    }
+    /// Executed deals of all accounts, in order of execution
+    pub fn trades(&self) -> Vec<PaperTrade> {
+        self.state.lock().unwrap().trades.clone()
+    }
+    /// Current portfolio of account
+    pub(crate) fn portfolio(&self, account_id: &str) -> std::option::Option<PortfolioResponse> {
+        self.state.lock().unwrap().portfolio(account_id)
+    }
+    /// Add money to account, account is created if not exists
+    pub fn pay_in(&self, account_id: &str, amount: impl Into<Quotation>) {
+        self.state.lock().unwrap().accounts.entry(account_id.to_owned()).or_default().money += amount.into();
+    }
+    /// Set current price of instrument and execute matching orders
+    pub fn set_price(&self, instrument_id: &str, price: Quotation) {
+        let mut events = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.prices.insert(instrument_id.to_owned(), price);
+            let orders: Vec<_> = state.orders.values().filter(|o| o.is_active() && o.instrument_id == instrument_id).map(|o| o.order_id.clone()).collect();
+            for order_id in orders {
+                state.execute(&order_id, &mut events);
+            }
+        }
+        self.publish(events);
+    }
+    /// Update prices by market data: last prices, trades and candles (by close price).
+    /// Market data is also sent to subscribed market data streams of paper api
+    pub fn process(&self, response: &StreamResponse) {
+        use market_data_response::Payload;
+        let (figi, uid, price, payload) = match response {
+            StreamResponse::LastPrice(p) => (&p.figi, &p.instrument_uid, p.price, Payload::LastPrice(p.clone())),
+            StreamResponse::Trade(t) => (&t.figi, &t.instrument_uid, t.price, Payload::Trade(t.clone())),
+            StreamResponse::Candle(c) => (&c.figi, &c.instrument_uid, c.close, Payload::Candle(c.clone())),
+            _ => return,
+        };
+        if let Some(price) = price {
+            for id in [figi, uid].into_iter().filter(|id| !id.is_empty()) {
+                self.set_price(id, price);
+            }
+        }
+        self.publish(vec![PaperEvent::Market(MarketDataResponse { payload: Some(payload) })]);
+    }
+    fn publish(&self, events: Vec<PaperEvent>) {
+        for event in events {
+            // no streams is not an error
+            let _ = self.events.send(event);
+        }
+    }
+    /// Post order, replacing active order `replaced`. Replaced order is cancelled only if new order is accepted
+    fn post(&self, order: PaperOrder, replaced: std::option::Option<&str>) -> Result<PaperOrder, Box<tonic::Status>> {
+        let mut events = Vec::new();
+        let order = {
+            let mut state = self.state.lock().unwrap();
+            state.account(&order.account_id)?;
+            if order.lots <= 0 {
+                return Err(Box::new(tonic::Status::invalid_argument("quantity must be positive")));
+            }
+            if let Some(existing) = state.orders.values().find(|o| !order.request_id.is_empty() && o.request_id == order.request_id && o.account_id == order.account_id) {
+                return Ok(existing.clone());
+            }
+            if let Some(replaced) = replaced {
+                if !state.order(&order.account_id, replaced)?.is_active() {
+                    return Err(Box::new(tonic::Status::failed_precondition(format!("order {replaced} is not active"))));
+                }
+            }
+            let price = match (order.order_type, order.limit) {
+                (OrderType::Limit, Some(limit)) => limit,
+                (OrderType::Limit, None) => return Err(Box::new(tonic::Status::invalid_argument("price is required for limit order"))),
+                _ => state.prices.get(&order.instrument_id).copied()
+                    .ok_or_else(|| Box::new(ErrorCode::InstrumentNotAvailable.status("no price of instrument")))?,
+            };
+            // replacing order has id of replaced one, so its reserve is not counted
+            state.check(&order, price)?;
+            if let Some(replaced) = replaced {
+                state.set_status(replaced, OrderExecutionReportStatus::ExecutionReportStatusCancelled, &mut events);
+            }
+            let order_id = format!("paper-{}", state.next_id);
+            state.next_id += 1;
+            let order = PaperOrder { order_id: order_id.clone(), created_at: state.now(), ..order };
+            let deferred = order.order_type != OrderType::Limit && state.fill.price == FillPrice::Next;
+            events.push(PaperEvent::OrderState(state.order_state_response(&order)));
+            state.orders.insert(order_id.clone(), order);
+            if !deferred {
+                state.execute(&order_id, &mut events);
+            }
+            state.orders[&order_id].clone()
+        };
+        self.publish(events);
+        Ok(order)
+    }
+    fn cancel(&self, account_id: &str, order_id: &str) -> Result<PaperOrder, Box<tonic::Status>> {
+        let mut events = Vec::new();
+        let order = {
+            let mut state = self.state.lock().unwrap();
+            if !state.order(account_id, order_id)?.is_active() {
+                return Err(Box::new(tonic::Status::failed_precondition(format!("order {order_id} is not active"))));
+            }
+            state.set_status(order_id, OrderExecutionReportStatus::ExecutionReportStatusCancelled, &mut events);
+            state.orders[order_id].clone()
+        };
+        self.publish(events);
+        Ok(order)
+    }
+    fn post_order(&self, req: PostOrderRequest) -> Result<PostOrderResponse, Box<tonic::Status>> {
+        let order = self.post(PaperOrder {
+            order_id: String::new(),
+            request_id: req.order_id,
+            account_id: req.account_id,
+            instrument_id: instrument_key(req.figi.as_deref().unwrap_or_default(), &req.instrument_id),
+            direction: OrderDirection::try_from(req.direction).unwrap_or_default(),
+            order_type: OrderType::try_from(req.order_type).unwrap_or_default(),
+            lots: req.quantity,
+            limit: req.price,
+            status: OrderExecutionReportStatus::ExecutionReportStatusNew,
+            created_at: Default::default(),
+            trades: Vec::new(),
+            commission: Default::default(),
+        }, None)?;
+        Ok(self.post_order_response(&order))
+    }
+    fn post_order_response(&self, order: &PaperOrder) -> PostOrderResponse {
+        let (state, now) = {
+            let state = self.state.lock().unwrap();
+            (state.order_state(order), state.now())
+        };
+        PostOrderResponse {
+            order_id: state.order_id,
+            execution_report_status: state.execution_report_status,
+            lots_requested: state.lots_requested,
+            lots_executed: state.lots_executed,
+            initial_order_price: state.initial_order_price,
+            executed_order_price: state.executed_order_price,
+            total_order_amount: state.total_order_amount,
+            figi: state.figi,
+            direction: state.direction,
+            initial_security_price: state.initial_security_price,
+            order_type: state.order_type,
+            initial_order_price_pt: order.limit,
+            instrument_uid: state.instrument_uid,
+            order_request_id: state.order_request_id,
+            response_metadata: Some(ResponseMetadata { tracking_id: String::new(), server_time: Some(now) }),
+            ..Default::default()
+        }
+    }
+    fn post_order_async(&self, req: PostOrderAsyncRequest) -> Result<PostOrderAsyncResponse, Box<tonic::Status>> {
+        let res = self.post_order(PostOrderRequest {
+            quantity: req.quantity,
+            price: req.price,
+            direction: req.direction,
+            account_id: req.account_id,
+            order_type: req.order_type,
+            order_id: req.order_id,
+            instrument_id: req.instrument_id,
+            ..Default::default()
+        })?;
+        Ok(PostOrderAsyncResponse { order_request_id: res.order_request_id, execution_report_status: res.execution_report_status, trade_intent_id: None })
+    }
+    fn replace_order(&self, req: ReplaceOrderRequest) -> Result<PostOrderResponse, Box<tonic::Status>> {
+        let order = self.state.lock().unwrap().order(&req.account_id, &req.order_id)?.clone();
+        let order = self.post(PaperOrder {
+            request_id: req.idempotency_key,
+            lots: req.quantity,
+            limit: req.price.or(order.limit),
+            status: OrderExecutionReportStatus::ExecutionReportStatusNew,
+            trades: Vec::new(),
+            commission: Default::default(),
+            ..order
+        }, Some(&req.order_id))?;
+        Ok(self.post_order_response(&order))
+    }
+    fn cancel_order(&self, req: CancelOrderRequest) -> Result<CancelOrderResponse, Box<tonic::Status>> {
+        self.cancel(&req.account_id, &req.order_id)?;
+        Ok(CancelOrderResponse { time: Some(self.state.lock().unwrap().now()), response_metadata: None })
+    }
+    fn get_orders(&self, req: GetOrdersRequest) -> Result<GetOrdersResponse, Box<tonic::Status>> {
+        let mut state = self.state.lock().unwrap();
+        state.account(&req.account_id)?;
+        let orders = state.orders.values().filter(|o| o.account_id == req.account_id && o.is_active()).map(|o| state.order_state(o)).collect();
+        Ok(GetOrdersResponse { orders })
+    }
+    fn get_order_state(&self, req: GetOrderStateRequest) -> Result<OrderState, Box<tonic::Status>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.order_state(state.order(&req.account_id, &req.order_id)?))
+    }
+    fn get_accounts(&self, _: GetAccountsRequest) -> Result<GetAccountsResponse, Box<tonic::Status>> {
+        let state = self.state.lock().unwrap();
+        let accounts = state.accounts.keys().map(|id| Account {
+            id: id.clone(),
+            name: id.clone(),
+            r#type: AccountType::Tinkoff.into(),
+            status: AccountStatus::Open.into(),
+            access_level: AccessLevel::AccountAccessLevelFullAccess.into(),
+            ..Default::default()
+        }).collect();
+        Ok(GetAccountsResponse { accounts })
+    }
+    fn get_info(&self, _: GetInfoRequest) -> Result<GetInfoResponse, Box<tonic::Status>> {
+        Ok(GetInfoResponse { tariff: "paper".into(), ..Default::default() })
+    }
+    fn get_portfolio(&self, req: PortfolioRequest) -> Result<PortfolioResponse, Box<tonic::Status>> {
+        let mut state = self.state.lock().unwrap();
+        state.account(&req.account_id)?;
+        Ok(state.portfolio(&req.account_id).unwrap_or_default())
+    }
+    fn get_positions(&self, req: PositionsRequest) -> Result<PositionsResponse, Box<tonic::Status>> {
+        let mut state = self.state.lock().unwrap();
+        state.account(&req.account_id)?;
+        Ok(state.positions(&req.account_id).unwrap_or_default())
+    }
+    fn get_last_prices(&self, req: GetLastPricesRequest) -> Result<GetLastPricesResponse, Box<tonic::Status>> {
+        let state = self.state.lock().unwrap();
+        let last_prices = req.instrument_id.iter().chain(req.figi.iter()).filter_map(|id| state.prices.get(id).map(|price| LastPrice {
+            figi: id.clone(),
+            price: Some(*price),
+            time: None,
+            instrument_uid: id.clone(),
+        })).collect();
+        Ok(GetLastPricesResponse { last_prices })
+    }
+    /// Stream of events, filtered by request
+    fn events_stream<Req, T, S>(&self, req: Req, mut sender: S, filter: fn(&Req, &PaperEvent) -> bool, initial: Vec<T>, convert: fn(PaperEvent) -> std::option::Option<T>) -> StreamHandle<Req>
+    where Req: Clone + Send + Sync + 'static, T: Send + 'static, S: futures::Sink<T> + Unpin + Send + 'static {
+        let (reporter, monitor) = StreamMonitor::new();
+        let mut events = self.events.subscribe();
+        let (control, mut commands) = watch::channel((req, StreamControl::Running));
+        reporter.event(StreamEvent::Connected);
+        let handle = tokio::spawn(async move {
+            for x in initial {
+                if sender.send(x).await.is_err() {
+                    reporter.event(StreamEvent::Stopped("receiver is closed".into()));
+                    return;
+                }
+            }
+            let mut detached = false;
+            loop {
+                tokio::select! {
+                    changed = commands.changed(), if !detached => {
+                        if changed.is_err() {
+                            detached = true;
+                            continue;
+                        }
+                        let state = commands.borrow_and_update().1;
+                        match state {
+                            StreamControl::Stopped => {
+                                reporter.event(StreamEvent::Stopped("stopped by handle".into()));
+                                break;
+                            }
+                            StreamControl::Paused => reporter.event(StreamEvent::Paused),
+                            StreamControl::Running => reporter.event(StreamEvent::Resubscribed),
+                        }
+                    }
+                    event = events.recv() => {
+                        let event = match event {
+                            Ok(event) => event,
+                            Err(broadcast::error::RecvError::Lagged(n)) => {
+                                warn!("paper stream lagged, {n} events skipped");
+                                continue;
+                            }
+                            Err(broadcast::error::RecvError::Closed) => {
+                                reporter.event(StreamEvent::Stopped("paper api is dropped".into()));
+                                break;
+                            }
+                        };
+                        let skip = {
+                            let current = commands.borrow();
+                            current.1 == StreamControl::Paused || !filter(&current.0, &event)
+                        };
+                        let Some(x) = convert(event).filter(|_| !skip) else { continue };
+                        reporter.message();
+                        if sender.send(x).await.is_err() {
+                            reporter.event(StreamEvent::Stopped("receiver is closed".into()));
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        StreamHandle::new(control, monitor, handle)
+    }
+}
+
+macro_rules! paper_sender_impl {
+    (handled: $($res:ty = $handler:ident($req:ty),)+) => {$(
+        impl OwnedSender<$req, $res> for PaperApi {
+            fn send_and_back(self, req: $req) -> impl Future<Output = (Self, Result<$res, tonic::Status>)> + Send {
+                let res = self.$handler(req).map_err(|e| *e);
+                async move { (self, res) }
+            }
+            fn send(&self, req: $req) -> impl Future<Output = Result<$res, tonic::Status>> + Send {
+                let res = self.$handler(req).map_err(|e| *e);
+                async move { res }
+            }
+            fn method_name() -> &'static str {
+                <Api as OwnedSender<$req, $res>>::method_name()
+            }
+        }
+    )+};
+    (unimplemented: $($res:ty = $req:ty,)+) => {$(
+        impl OwnedSender<$req, $res> for PaperApi {
+            fn send_and_back(self, _: $req) -> impl Future<Output = (Self, Result<$res, tonic::Status>)> + Send {
+                async move { (self, Err(tonic::Status::unimplemented(concat!("paper api does not support ", stringify!($req))))) }
+            }
+            fn send(&self, _: $req) -> impl Future<Output = Result<$res, tonic::Status>> + Send {
+                async move { Err(tonic::Status::unimplemented(concat!("paper api does not support ", stringify!($req)))) }
+            }
+            fn method_name() -> &'static str {
+                <Api as OwnedSender<$req, $res>>::method_name()
+            }
+        }
+    )+};
+}
+
+paper_sender_impl!(handled:
+    PostOrderResponse = post_order(PostOrderRequest),
+    PostOrderAsyncResponse = post_order_async(PostOrderAsyncRequest),
+    PostOrderResponse = replace_order(ReplaceOrderRequest),
+    CancelOrderResponse = cancel_order(CancelOrderRequest),
+    GetOrdersResponse = get_orders(GetOrdersRequest),
+    OrderState = get_order_state(GetOrderStateRequest),
+    GetAccountsResponse = get_accounts(GetAccountsRequest),
+    GetInfoResponse = get_info(GetInfoRequest),
+    PortfolioResponse = get_portfolio(PortfolioRequest),
+    PositionsResponse = get_positions(PositionsRequest),
+    GetLastPricesResponse = get_last_prices(GetLastPricesRequest),
+);
+
+paper_sender_impl!(unimplemented:
+    BondResponse = InstrumentRequest,
+    BondsResponse = InstrumentsRequest,
+    CreateFavoriteGroupResponse = CreateFavoriteGroupRequest,
+    DeleteFavoriteGroupResponse = DeleteFavoriteGroupRequest,
+    CurrenciesResponse = InstrumentsRequest,
+    CurrencyResponse = InstrumentRequest,
+    EditFavoritesResponse = EditFavoritesRequest,
+    EtfResponse = InstrumentRequest,
+    EtfsResponse = InstrumentsRequest,
+    FindInstrumentResponse = FindInstrumentRequest,
+    FutureResponse = InstrumentRequest,
+    FuturesResponse = InstrumentsRequest,
+    GetAccruedInterestsResponse = GetAccruedInterestsRequest,
+    AssetResponse = AssetRequest,
+    GetAssetFundamentalsResponse = GetAssetFundamentalsRequest,
+    GetAssetReportsResponse = GetAssetReportsRequest,
+    AssetsResponse = AssetsRequest,
+    GetBondCouponsResponse = GetBondCouponsRequest,
+    GetBondEventsResponse = GetBondEventsRequest,
+    Brand = GetBrandRequest,
+    GetBrandsResponse = GetBrandsRequest,
+    GetConsensusForecastsResponse = GetConsensusForecastsRequest,
+    GetCountriesResponse = GetCountriesRequest,
+    GetDividendsResponse = GetDividendsRequest,
+    GetFavoriteGroupsResponse = GetFavoriteGroupsRequest,
+    GetFavoritesResponse = GetFavoritesRequest,
+    GetForecastResponse = GetForecastRequest,
+    GetFuturesMarginResponse = GetFuturesMarginRequest,
+    InstrumentResponse = InstrumentRequest,
+    RiskRatesResponse = RiskRatesRequest,
+    IndicativesResponse = IndicativesRequest,
+    OptionResponse = InstrumentRequest,
+    OptionsResponse = FilterOptionsRequest,
+    ShareResponse = InstrumentRequest,
+    SharesResponse = InstrumentsRequest,
+    TradingSchedulesResponse = TradingSchedulesRequest,
+    GetCandlesResponse = GetCandlesRequest,
+    GetClosePricesResponse = GetClosePricesRequest,
+    GetLastTradesResponse = GetLastTradesRequest,
+    GetOrderBookResponse = GetOrderBookRequest,
+    GetTechAnalysisResponse = GetTechAnalysisRequest,
+    GetTradingStatusResponse = GetTradingStatusRequest,
+    GetTradingStatusesResponse = GetTradingStatusesRequest,
+    GetDividendsForeignIssuerResponse = GetDividendsForeignIssuerRequest,
+    OperationsResponse = OperationsRequest,
+    GetOperationsByCursorResponse = GetOperationsByCursorRequest,
+    WithdrawLimitsResponse = WithdrawLimitsRequest,
+    GetMaxLotsResponse = GetMaxLotsRequest,
+    GetOrderPriceResponse = GetOrderPriceRequest,
+    GetSignalsResponse = GetSignalsRequest,
+    GetStrategiesResponse = GetStrategiesRequest,
+    CancelStopOrderResponse = CancelStopOrderRequest,
+    GetStopOrdersResponse = GetStopOrdersRequest,
+    PostStopOrderResponse = PostStopOrderRequest,
+    GetMarginAttributesResponse = GetMarginAttributesRequest,
+    GetUserTariffResponse = GetUserTariffRequest,
+);
+
+impl AnyRequestor for PaperApi {}
+
+impl<T> StartStream<OrderStateStreamRequest, T> for PaperApi where T: From<OrderStateStreamResponse> + Send + 'static {
+    fn start_stream<S>(&self, req: OrderStateStreamRequest, sender: S) -> impl Future<Output = Result<StreamHandle<OrderStateStreamRequest>, tonic::Status>> + Send
+    where S: futures::Sink<T> + Unpin + Send + 'static {
+        let handle = self.events_stream(req, sender, |r, e| e.for_accounts(&r.accounts), Vec::new(), |e| match e {
+            PaperEvent::OrderState(x) => Some(OrderStateStreamResponse { payload: Some(order_state_stream_response::Payload::OrderState(x)) }.into()),
+            _ => None,
+        });
+        async move { Ok(handle) }
+    }
+    fn stream_name() -> &'static str {
+        <Api as StartStream<OrderStateStreamRequest, StreamResponse>>::stream_name()
+    }
+}
+
+impl<T> StartStream<TradesStreamRequest, T> for PaperApi where T: From<TradesStreamResponse> + Send + 'static {
+    fn start_stream<S>(&self, req: TradesStreamRequest, sender: S) -> impl Future<Output = Result<StreamHandle<TradesStreamRequest>, tonic::Status>> + Send
+    where S: futures::Sink<T> + Unpin + Send + 'static {
+        let handle = self.events_stream(req, sender, |r, e| e.for_accounts(&r.accounts), Vec::new(), |e| match e {
+            PaperEvent::Trades(x) => Some(TradesStreamResponse { payload: Some(trades_stream_response::Payload::OrderTrades(x)) }.into()),
+            _ => None,
+        });
+        async move { Ok(handle) }
+    }
+    fn stream_name() -> &'static str {
+        <Api as StartStream<TradesStreamRequest, StreamResponse>>::stream_name()
+    }
+}
+
+impl<T> StartStream<PositionsStreamRequest, T> for PaperApi where T: From<PositionsStreamResponse> + Send + 'static {
+    fn start_stream<S>(&self, req: PositionsStreamRequest, sender: S) -> impl Future<Output = Result<StreamHandle<PositionsStreamRequest>, tonic::Status>> + Send
+    where S: futures::Sink<T> + Unpin + Send + 'static {
+        let initial = if req.with_initial_positions {
+            let state = self.state.lock().unwrap();
+            let accounts: Vec<_> = if req.accounts.is_empty() { state.accounts.keys().cloned().collect() } else { req.accounts.clone() };
+            accounts.iter().filter_map(|a| state.positions(a)).map(|p| {
+                PositionsStreamResponse { payload: Some(positions_stream_response::Payload::InitialPositions(p)) }.into()
+            }).collect()
+        } else { Vec::new() };
+        let handle = self.events_stream(req, sender, |r, e| e.for_accounts(&r.accounts), initial, |e| match e {
+            PaperEvent::Position(x) => Some(PositionsStreamResponse { payload: Some(positions_stream_response::Payload::Position(x)) }.into()),
+            _ => None,
+        });
+        async move { Ok(handle) }
+    }
+    fn stream_name() -> &'static str {
+        <Api as StartStream<PositionsStreamRequest, StreamResponse>>::stream_name()
+    }
+}
+
+impl<T> StartStream<PortfolioStreamRequest, T> for PaperApi where T: From<PortfolioStreamResponse> + Send + 'static {
+    fn start_stream<S>(&self, req: PortfolioStreamRequest, sender: S) -> impl Future<Output = Result<StreamHandle<PortfolioStreamRequest>, tonic::Status>> + Send
+    where S: futures::Sink<T> + Unpin + Send + 'static {
+        let handle = self.events_stream(req, sender, |r, e| e.for_accounts(&r.accounts), Vec::new(), |e| match e {
+            PaperEvent::Portfolio(x) => Some(PortfolioStreamResponse { payload: Some(portfolio_stream_response::Payload::Portfolio(x)) }.into()),
+            _ => None,
+        });
+        async move { Ok(handle) }
+    }
+    fn stream_name() -> &'static str {
+        <Api as StartStream<PortfolioStreamRequest, StreamResponse>>::stream_name()
+    }
+}
+
+/// Market data is not produced by paper api, it is only market data passed to [PaperApi::process]
+impl<T> StartStream<MarketDataServerSideStreamRequest, T> for PaperApi where T: From<MarketDataResponse> + Send + 'static {
+    fn start_stream<S>(&self, req: MarketDataServerSideStreamRequest, sender: S) -> impl Future<Output = Result<StreamHandle<MarketDataServerSideStreamRequest>, tonic::Status>> + Send
+    where S: futures::Sink<T> + Unpin + Send + 'static {
+        let handle = self.events_stream(req, sender, |r, e| e.for_market(r), Vec::new(), |e| match e {
+            PaperEvent::Market(x) => Some(x.into()),
+            _ => None,
+        });
+        async move { Ok(handle) }
+    }
+    fn stream_name() -> &'static str {
+        <Api as StartStream<MarketDataServerSideStreamRequest, StreamResponse>>::stream_name()
+    }
+}
+
+impl AnyStream<StreamResponse> for PaperApi {}
+
+#[tokio::test]
+async fn test_paper() {
+    use crate::Requestor;
+    use futures::StreamExt;
+    let api = PaperApi::new().with_account("acc", 1000.0).with_instrument("x", 10);
+    let (s, mut r) = futures::channel::mpsc::channel::<StreamResponse>(10);
+    let _handle = api.start_stream(TradesStreamRequest::default(), s).await.unwrap();
+    let order = |direction: OrderDirection, order_type: OrderType, quantity, price: std::option::Option<Quotation>| PostOrderRequest {
+        instrument_id: "x".into(), account_id: "acc".into(), quantity, price, direction: direction.into(), order_type: order_type.into(), ..Default::default()
+    };
+    let err = api.request(order(OrderDirection::Buy, OrderType::Market, 1, None)).await.unwrap_err();
+    assert_eq!(crate::Error::from(err).code, ErrorCode::InstrumentNotAvailable);
+    api.set_price("x", 50.0.into());
+    let err = api.request(order(OrderDirection::Buy, OrderType::Market, 3, None)).await.unwrap_err();
+    assert_eq!(crate::Error::from(err).code, ErrorCode::NotEnoughBalance);
+    for quantity in [0, -1] {
+        let err = api.request(order(OrderDirection::Buy, OrderType::Market, quantity, None)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+    let res = api.request(order(OrderDirection::Buy, OrderType::Limit, 1, Some(40.0.into()))).await.unwrap();
+    assert_eq!(res.lots_executed, 0);
+    let replace = |quantity| ReplaceOrderRequest { account_id: "acc".into(), order_id: res.order_id.clone(), quantity, ..Default::default() };
+    let err = api.request(replace(3)).await.unwrap_err();
+    assert_eq!(crate::Error::from(err).code, ErrorCode::NotEnoughBalance);
+    let replaced: PostOrderResponse = api.request(replace(1)).await.unwrap();
+    assert_ne!(replaced.order_id, res.order_id);
+    let orders: GetOrdersResponse = api.request(GetOrdersRequest { account_id: "acc".into() }).await.unwrap();
+    assert_eq!(orders.orders.len(), 1);
+    let positions: PositionsResponse = api.request(PositionsRequest { account_id: "acc".into() }).await.unwrap();
+    assert_eq!((positions.money[0].units, positions.blocked[0].units), (600, 400));
+    let err = api.request(order(OrderDirection::Buy, OrderType::Market, 2, None)).await.unwrap_err();
+    assert_eq!(crate::Error::from(err).code, ErrorCode::NotEnoughBalance);
+    api.set_price("x", 40.0.into());
+    let Some(StreamResponse::OrderTrades(trades)) = r.next().await else { panic!("trades expected") };
+    assert_eq!(trades.trades[0].quantity, 10);
+    let positions: PositionsResponse = api.request(PositionsRequest { account_id: "acc".into() }).await.unwrap();
+    assert_eq!(positions.money[0].units, 600);
+    assert_eq!(positions.securities[0].balance, 10);
+    let err = api.request(order(OrderDirection::Sell, OrderType::Market, 2, None)).await.unwrap_err();
+    assert_eq!(crate::Error::from(err).code, ErrorCode::NotEnoughAssets);
+    let res = api.request(order(OrderDirection::Sell, OrderType::Limit, 1, Some(100.0.into()))).await.unwrap();
+    let err = api.request(order(OrderDirection::Sell, OrderType::Market, 1, None)).await.unwrap_err();
+    assert_eq!(crate::Error::from(err).code, ErrorCode::NotEnoughAssets);
+    api.request(CancelOrderRequest { account_id: "acc".into(), order_id: res.order_id }).await.unwrap();
+    api.request(order(OrderDirection::Sell, OrderType::Market, 1, None)).await.unwrap();
+    let portfolio: PortfolioResponse = api.request(PortfolioRequest { account_id: "acc".into(), currency: None }).await.unwrap();
+    assert!(portfolio.positions.is_empty());
+    assert_eq!(portfolio.total_amount_portfolio.unwrap().units, 1000);
+}
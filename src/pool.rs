@@ -1,19 +1,140 @@
 //! Simple rounding pool implementation
 use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
 
-use tokio::task::JoinHandle;
+use tokio::sync::{Notify, OwnedSemaphorePermit};
+use tonic::Code;
 
-use crate::stream::{AnyStream, StartStream};
-use crate::requestor::{AnyRequestor, OwnedSender};
+use crate::limits::{PermitSink, TariffLimits};
+use crate::stream::{AnyStream, ReconnectPolicy, StartStream, StreamHandle};
+use crate::requestor::{AnyRequestor, OwnedSender, Response};
+use crate::t_types::{GetInfoRequest, GetInfoResponse, GetUserTariffRequest, GetUserTariffResponse};
 use crate::StreamResponse;
+
+/// Strategy of choosing connection for request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Selection {
+    /// Connections are used in turn, one request per connection at time
+    #[default]
+    RoundRobin,
+    /// Connection with least requests in flight, connections are shared between requests
+    LeastInFlight,
+}
+
+/// Settings of health checks
+#[derive(Debug, Clone)]
+pub struct HealthConfig {
+    /// Consecutive failures, after which connection is evicted
+    pub max_failures: u32,
+    /// Interval of probing of evicted connection with `GetInfoRequest`
+    pub probe_interval: Duration,
+    /// Weight of last request in latency EWMA
+    pub latency_alpha: f64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self { max_failures: 3, probe_interval: Duration::from_secs(10), latency_alpha: 0.2 }
+    }
+}
+
+/// Health of connection in pool
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemberHealth {
+    /// Consecutive failures
+    pub failures: u32,
+    /// EWMA of latency
+    pub latency: Option<Duration>,
+    pub in_flight: usize,
+    pub evicted: bool,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    failures: AtomicU32,
+    latency_us: AtomicU64,
+    in_flight: AtomicUsize,
+    evicted: AtomicBool,
+}
+
+impl Health {
+    fn is_evicted(&self) -> bool {
+        self.evicted.load(Ordering::Relaxed)
+    }
+    fn snapshot(&self) -> MemberHealth {
+        let latency = self.latency_us.load(Ordering::Relaxed);
+        MemberHealth {
+            failures: self.failures.load(Ordering::Relaxed),
+            latency: (latency > 0).then(|| Duration::from_micros(latency)),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            evicted: self.is_evicted(),
+        }
+    }
+    fn record_latency(&self, latency: Duration, alpha: f64) {
+        let sample = (latency.as_micros() as u64).max(1);
+        let _ = self.latency_us.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |old| Some(match old {
+            0 => sample,
+            old => (alpha * sample as f64 + (1.0 - alpha) * old as f64) as u64,
+        }));
+    }
+    /// Returns true if connection must be evicted
+    fn record(&self, ok: bool, latency: Duration, config: &HealthConfig) -> bool {
+        self.record_latency(latency, config.latency_alpha);
+        if ok {
+            self.failures.store(0, Ordering::Relaxed);
+            return false;
+        }
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        failures >= config.max_failures && !self.evicted.swap(true, Ordering::Relaxed)
+    }
+    fn reset(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+        self.evicted.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Failures of connection, not of request
+fn is_failure(status: &tonic::Status) -> bool {
+    matches!(status.code(), Code::Unavailable | Code::DeadlineExceeded | Code::Unknown)
+}
+
+struct Member<T> {
+    api: T,
+    health: Health,
+    /// notified, when reference to member is released
+    released: Arc<Notify>,
+}
+
+impl<T> Member<T> {
+    fn new(api: T, health: Health) -> Arc<Self> {
+        Arc::new(Self { api, health, released: Arc::new(Notify::new()) })
+    }
+}
+
+/// Release reference to member and wake up waiting for exclusive use of it
+fn unshare<T>(member: Arc<Member<T>>) {
+    let released = member.released.clone();
+    drop(member);
+    released.notify_waiters();
+}
+
+type Probe<T> = for<'a> fn(&'a T) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>>;
+
+fn probe<T: OwnedSender<GetInfoRequest, GetInfoResponse> + Sync>(api: &T) -> Pin<Box<dyn Future<Output = bool> + Send + '_>> {
+    Box::pin(async move { api.send(GetInfoRequest {}).await.is_ok() })
+}
+
 /// Pool for invest api connections
 /// # Examples:
 /// ```rust
 /// #[tokio::main]
 /// async fn main() {
 ///    use yatis::*;
-///    let token = std::env::var("SANDBOX_TOKEN").expect("need to set env var 'TOKEN'");
-///    let api = yatis::SandboxApi::create_invest_service(token).unwrap();
+/// #  let mock = yatis::mock::MockServer::start().await.unwrap();
+///    let api = mock.api(); // or SandboxApi::create_invest_service(token).unwrap()
 ///    let pool = ApiPool::new(api.clone());
 ///    pool.add(api); //now we have pool of 2 connections
 ///    trading(pool).await;
@@ -21,60 +142,439 @@ use crate::StreamResponse;
 /// async fn trading(api: impl yatis::InvestApi) {
 ///     /* do some trading */
 /// }
-pub struct ApiPool<T>(deadqueue::unlimited::Queue<T>);
+/// ```
+pub struct ApiPool<T> {
+    queue: deadqueue::unlimited::Queue<Arc<Member<T>>>,
+    members: Mutex<Vec<Arc<Member<T>>>>,
+    selection: Selection,
+    wait_timeout: Option<Duration>,
+    max_size: Option<usize>,
+    health: Option<(HealthConfig, Probe<T>)>,
+    limits: Option<Arc<TariffLimits>>,
+}
 
 impl<Api: Send + 'static> ApiPool<Api> {
     pub fn new(api: Api) -> Self {
-        let q = deadqueue::unlimited::Queue::new();
-        q.push(api);
-        Self(q)
+        let pool = Self {
+            queue: deadqueue::unlimited::Queue::new(),
+            members: Mutex::new(Vec::new()),
+            selection: Selection::default(),
+            wait_timeout: None,
+            max_size: None,
+            health: None,
+            limits: None,
+        };
+        pool.add(api);
+        pool
+    }
+    /// Create pool with limits of user's tariff. Requests over limit wait for free quota,
+    /// streams over limit are rejected with `RESOURCE_EXHAUSTED`.
+    /// # Examples:
+    /// ```rust
+    /// # #[tokio::main]
+    /// # async fn main() {
+    ///    use yatis::*;
+    /// #   let mock = yatis::mock::MockServer::start().await.unwrap();
+    /// #   mock.respond(t_types::GetUserTariffResponse::default());
+    ///    let api = mock.api(); // or Api::create_invest_service(token).unwrap()
+    ///    let pool = ApiPool::with_tariff(api).await.unwrap();
+    /// # }
+    /// ```
+    pub async fn with_tariff(api: Api) -> Result<Self, tonic::Status> where Api: OwnedSender<GetUserTariffRequest, GetUserTariffResponse> {
+        let limits = TariffLimits::load(&api).await?;
+        Ok(Self::new(api).with_limits(limits))
+    }
+    /// Set limits for requests and streams. The same limits can be shared between pools with the same token.
+    pub fn with_limits(mut self, limits: impl Into<Arc<TariffLimits>>) -> Self {
+        self.limits = Some(limits.into());
+        self
+    }
+    /// Set strategy of choosing connection
+    pub fn with_selection(mut self, selection: Selection) -> Self {
+        self.selection = selection;
+        self
     }
+    /// Evict connections after consecutive failures and return them after successful probe.
+    /// # Examples:
+    /// ```rust
+    /// # #[tokio::main]
+    /// # async fn main() {
+    ///    use yatis::*;
+    ///    use yatis::pool::{HealthConfig, Selection};
+    /// #   let mock = yatis::mock::MockServer::start().await.unwrap();
+    /// #   mock.respond(t_types::GetInfoResponse::default());
+    ///    let api = mock.api(); // or Api::create_invest_service(token).unwrap()
+    ///    let pool = ApiPool::new(api.clone())
+    ///        .with_health(HealthConfig::default())
+    ///        .with_selection(Selection::LeastInFlight);
+    ///    pool.add(api);
+    ///    println!("{:?}", pool.request(t_types::GetInfoRequest{}).await);
+    ///    println!("{:?}", pool.health());
+    /// # }
+    /// ```
+    pub fn with_health(mut self, config: HealthConfig) -> Self where Api: OwnedSender<GetInfoRequest, GetInfoResponse> + Sync {
+        self.health = Some((config, probe::<Api>));
+        self
+    }
+    /// Set timeout of waiting for free connection, after which requests fail with `RESOURCE_EXHAUSTED`
+    pub fn with_wait_timeout(mut self, timeout: Duration) -> Self {
+        self.wait_timeout = Some(timeout);
+        self
+    }
+    /// Set max count of connections in pool
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = Some(max_size.max(1));
+        self
+    }
+    /// Add connection to pool. Connection over max size is dropped
     pub fn add(&self, api: Api) {
-        self.0.push(api);
+        if self.try_add(api).is_err() {
+            log::warn!("pool is full, connection is dropped");
+        }
+    }
+    /// Add connection to pool, or return it back if pool is full
+    pub fn try_add(&self, api: Api) -> Result<(), Api> {
+        let mut members = self.members.lock().unwrap();
+        if self.max_size.is_some_and(|max| members.len() >= max) {
+            return Err(api);
+        }
+        let member = Member::new(api, Health::default());
+        members.push(member.clone());
+        self.queue.push(member);
+        Ok(())
+    }
+    /// Count of connections in pool
+    pub fn size(&self) -> usize {
+        self.members.lock().unwrap().len()
+    }
+    pub fn max_size(&self) -> Option<usize> {
+        self.max_size
     }
-    pub async fn with_api<T, Fut: Future<Output=(impl Into<Api>, T)>, Fun: FnOnce(Api) -> Fut>(&self, fun: Fun) -> T where T: Send+Sized, Fut: Send{
-        let api = self.0.pop().await;
+    /// Health of all connections
+    pub fn health(&self) -> Vec<MemberHealth> {
+        self.members.lock().unwrap().iter().map(|m| m.health.snapshot()).collect()
+    }
+    /// Take connection from pool for exclusive use, returned connection is added back to pool as healthy
+    pub async fn with_api<T, Fut: Future<Output=(impl Into<Api>, T)>, Fun: FnOnce(Api) -> Fut>(&self, fun: Fun) -> T where T: Send+Sized, Fut: Send {
+        let Member { api, health, .. } = self.take().await;
+        health.in_flight.fetch_add(1, Ordering::Relaxed);
+        let start = Instant::now();
         let (api, res) = fun(api).await;
-        self.0.push(api.into());
+        health.in_flight.fetch_sub(1, Ordering::Relaxed);
+        let alpha = self.health.as_ref().map(|(config, _)| config.latency_alpha).unwrap_or(HealthConfig::default().latency_alpha);
+        health.record_latency(start.elapsed(), alpha);
+        // probe of evicted connection is stopped on take, returned connection is checked again by requests
+        health.reset();
+        let member = Member::new(api.into(), health);
+        self.members.lock().unwrap().push(member.clone());
+        self.queue.push(member);
         res
     }
+    /// Remove next connection from pool, waiting for its requests in flight
+    async fn take(&self) -> Member<Api> {
+        let mut member = self.pop().await;
+        self.members.lock().unwrap().retain(|m| !Arc::ptr_eq(m, &member));
+        let released = member.released.clone();
+        loop {
+            let notified = released.notified();
+            tokio::pin!(notified);
+            // registered before check, so release between check and wait is not missed
+            notified.as_mut().enable();
+            match Arc::try_unwrap(member) {
+                Ok(member) => return member,
+                Err(shared) => member = shared,
+            }
+            notified.await;
+        }
+    }
+    /// Take connection from pool, waiting no longer than wait timeout. Connection returns to pool on drop of guard.
+    /// # Examples:
+    /// ```rust
+    /// # #[tokio::main]
+    /// # async fn main() {
+    ///    use std::time::Duration;
+    ///    use yatis::*;
+    /// #   let mock = yatis::mock::MockServer::start().await.unwrap();
+    /// #   mock.respond(t_types::GetInfoResponse::default());
+    ///    let api = mock.api(); // or Api::create_invest_service(token).unwrap()
+    ///    let pool = ApiPool::new(api).with_wait_timeout(Duration::from_secs(1));
+    ///    let api = pool.checkout().await.unwrap();
+    ///    println!("{:?}", api.request(t_types::GetInfoRequest{}).await);
+    /// # }
+    /// ```
+    pub async fn checkout(&self) -> Result<Checkout<'_, Api>, tonic::Status> {
+        let (member, requeue) = match self.wait_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.select()).await
+                .map_err(|_| tonic::Status::resource_exhausted("no free connection in pool"))?,
+            None => self.select().await,
+        };
+        Ok(Checkout::new(self, member, requeue))
+    }
+    /// Next connection in turn, evicted connections are skipped while there are others
+    async fn pop(&self) -> Arc<Member<Api>> {
+        let mut member = self.queue.pop().await;
+        for _ in 0..self.queue.len() {
+            if !member.health.is_evicted() {
+                break;
+            }
+            let Some(next) = self.queue.try_pop() else { break };
+            self.queue.push(member);
+            member = next;
+        }
+        member
+    }
+    /// Connection for request and flag, that it must be returned to queue
+    async fn select(&self) -> (Arc<Member<Api>>, bool) {
+        if self.selection == Selection::LeastInFlight {
+            if let Some(member) = self.least_in_flight() {
+                return (member, false);
+            }
+            // all connections are taken by with_api, wait for returned one
+        }
+        (self.pop().await, true)
+    }
+    fn least_in_flight(&self) -> Option<Arc<Member<Api>>> {
+        self.members.lock().unwrap().iter()
+            .min_by_key(|m| (m.health.is_evicted(), m.health.in_flight.load(Ordering::Relaxed)))
+            .cloned()
+    }
+    fn record(&self, member: &Arc<Member<Api>>, failed: bool, latency: Duration) where Api: Sync {
+        let Some((config, probe)) = &self.health else {
+            // without health checks connections are never evicted
+            member.health.record_latency(latency, HealthConfig::default().latency_alpha);
+            return;
+        };
+        if member.health.record(!failed, latency, config) {
+            log::warn!("connection is evicted from pool after {} failures", config.max_failures);
+            let member = Arc::downgrade(member);
+            tokio::spawn(probe_loop(member, config.probe_interval, *probe));
+        }
+    }
+}
+
+/// Connection, taken from [ApiPool]. Returns to pool on drop
+pub struct Checkout<'a, T: Send + 'static> {
+    pool: &'a ApiPool<T>,
+    member: Option<Arc<Member<T>>>,
+    requeue: bool,
+    start: Instant,
+}
+
+impl<'a, T: Send + 'static> Checkout<'a, T> {
+    fn new(pool: &'a ApiPool<T>, member: Arc<Member<T>>, requeue: bool) -> Self {
+        member.health.in_flight.fetch_add(1, Ordering::Relaxed);
+        Self { pool, member: Some(member), requeue, start: Instant::now() }
+    }
+    /// Return connection to pool, with result of request for health
+    fn finish(mut self, failed: bool) where T: Sync {
+        if let Some(member) = self.member.take() {
+            member.health.in_flight.fetch_sub(1, Ordering::Relaxed);
+            self.pool.record(&member, failed, self.start.elapsed());
+            self.release(member);
+        }
+    }
+    fn release(&self, member: Arc<Member<T>>) {
+        if self.requeue {
+            self.pool.queue.push(member);
+        } else {
+            unshare(member);
+        }
+    }
+}
+
+impl<T: Send + 'static> std::ops::Deref for Checkout<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.member.as_ref().expect("connection is not returned").api
+    }
 }
 
-impl<T:Clone> ApiPool<T> {
+impl<T: Send + 'static> Drop for Checkout<'_, T> {
+    fn drop(&mut self) {
+        if let Some(member) = self.member.take() {
+            member.health.in_flight.fetch_sub(1, Ordering::Relaxed);
+            self.release(member);
+        }
+    }
+}
+
+async fn probe_loop<T>(member: Weak<Member<T>>, interval: Duration, probe: Probe<T>) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(member) = member.upgrade() else { return };
+        let healthy = probe(&member.api).await;
+        if healthy {
+            log::info!("evicted connection is healthy again");
+            member.health.reset();
+        }
+        unshare(member);
+        if healthy {
+            return;
+        }
+    }
+}
+
+impl<T:Clone + Send + 'static> ApiPool<T> {
     pub async fn get(&self) -> T {
-        let res = self.0.pop().await;
-        self.0.push(res.clone());
-        res
+        let (member, requeue) = self.select().await;
+        Checkout::new(self, member, requeue).clone()
     }
 }
 
 
-impl<Api, Req, Res> OwnedSender<Req, Res> for ApiPool<Api> where Api: Send + 'static + OwnedSender<Req, Res>, Req: Send, Res: Send {
-    /// Do not use it!
+impl<Api, Req, Res> OwnedSender<Req, Res> for ApiPool<Api> where Api: Send + Sync + 'static + OwnedSender<Req, Res>, Req: Send, Res: Send {
+    /// Pool is returned back, connection is returned to pool after request
     fn send_and_back(self, req: Req) -> impl Future<Output = (Self,Result<Res, tonic::Status>)> {
-        log::warn!("Don use ApiPool::send_and_back! Please, use ApiPool::send");
         Box::pin(async move{
             let res = self.send(req).await;
             (self, res)
         })
     }
     fn send(&self, req: Req) -> impl Future<Output = Result<Res, tonic::Status>> + Send{
-        self.with_api(move |api|Box::pin(async move {
-            api.send_and_back(req).await
-        }))
+        Box::pin(async move {
+            if let Some(limits) = &self.limits {
+                limits.acquire_unary(Api::method_name()).await;
+            }
+            let api = self.checkout().await?;
+            let res = api.send(req).await;
+            api.finish(res.as_ref().is_err_and(is_failure));
+            res
+        })
+    }
+    fn send_with_meta(&self, req: Req) -> impl Future<Output = Result<Response<Res>, tonic::Status>> + Send {
+        Box::pin(async move {
+            if let Some(limits) = &self.limits {
+                limits.acquire_unary(Api::method_name()).await;
+            }
+            let api = self.checkout().await?;
+            let res = api.send_with_meta(req).await;
+            api.finish(res.as_ref().is_err_and(is_failure));
+            res
+        })
+    }
+    fn method_name() -> &'static str {
+        Api::method_name()
+    }
+}
+
+impl<Api: Send + 'static> ApiPool<Api> {
+    async fn stream_permit<Req, T>(&self) -> Result<Option<OwnedSemaphorePermit>, tonic::Status> where Api: StartStream<Req, T> {
+        match &self.limits {
+            Some(limits) => limits.acquire_stream(Api::stream_name()).map_err(|_|{
+                tonic::Status::resource_exhausted(format!("limit of streams {} is exceeded", Api::stream_name()))
+            }),
+            None => Ok(None),
+        }
     }
 }
 
-impl<Api, Req,T> StartStream<Req,T> for ApiPool<Api> where Api: StartStream<Req, T> + Clone + Send, Req: Send {
-    fn start_stream<S>(&self, req: Req, sender: S) -> impl Future<Output=Result<JoinHandle<()>, tonic::Status>> + Send
+impl<Api, Req,T> StartStream<Req,T> for ApiPool<Api> where Api: StartStream<Req, T> + Send + Sync + 'static, Req: Send {
+    fn start_stream<S>(&self, req: Req, sender: S) -> impl Future<Output=Result<StreamHandle<Req>, tonic::Status>> + Send
     where S: futures::Sink<T> + Unpin + Send + 'static {
         Box::pin(async move {
-            let api = self.0.pop().await;
-            self.0.push(api.clone());
-            api.start_stream(req, sender).await
+            let permit = self.stream_permit().await?;
+            let api = self.checkout().await?;
+            api.start_stream(req, PermitSink::new(sender, permit)).await
         })
     }
+    fn start_stream_with_policy<S>(&self, req: Req, sender: S, policy: ReconnectPolicy) -> impl Future<Output=Result<StreamHandle<Req>, tonic::Status>> + Send
+    where S: futures::Sink<T> + Unpin + Send + 'static {
+        Box::pin(async move {
+            let permit = self.stream_permit().await?;
+            let api = self.checkout().await?;
+            api.start_stream_with_policy(req, PermitSink::new(sender, permit), policy).await
+        })
+    }
+    fn stream_name() -> &'static str {
+        Api::stream_name()
+    }
 }
 
-impl<T: AnyRequestor + 'static> AnyRequestor for ApiPool<T> {}
-impl<T: AnyStream<StreamResponse> + Clone> AnyStream<StreamResponse> for ApiPool<T> {}
\ No newline at end of file
+impl<T: AnyRequestor + Sync + 'static> AnyRequestor for ApiPool<T> {}
+impl<T: AnyStream<StreamResponse> + Sync + 'static> AnyStream<StreamResponse> for ApiPool<T> {}
+
+#[test]
+fn test_health() {
+    let config = HealthConfig { max_failures: 2, ..Default::default() };
+    let health = Health::default();
+    assert!(!health.record(false, Duration::from_millis(100), &config));
+    assert!(health.record(false, Duration::from_millis(200), &config));
+    assert!(!health.record(false, Duration::from_millis(200), &config));
+    let snapshot = health.snapshot();
+    assert_eq!(snapshot.failures, 3);
+    assert!(snapshot.evicted);
+    assert_eq!(snapshot.latency, Some(Duration::from_micros(136_000)));
+    health.reset();
+    assert!(!health.record(true, Duration::from_millis(100), &config));
+    assert_eq!(health.snapshot().failures, 0);
+    assert!(!health.snapshot().evicted);
+}
+
+#[test]
+fn test_without_health() {
+    let pool = ApiPool::new(1);
+    let member = pool.members.lock().unwrap()[0].clone();
+    for _ in 0..3 {
+        pool.record(&member, true, Duration::from_millis(10));
+    }
+    let health = pool.health();
+    assert!(!health[0].evicted);
+    assert_eq!(health[0].latency, Some(Duration::from_millis(10)));
+}
+
+#[tokio::test]
+async fn test_with_api() {
+    let pool = ApiPool::new(1).with_selection(Selection::LeastInFlight);
+    let res = pool.with_api(|api| async move {
+        (api + 10, api * 2)
+    }).await;
+    assert_eq!(res, 2);
+    assert_eq!(pool.size(), 1);
+    assert_eq!(*pool.checkout().await.unwrap(), 11);
+    assert!(pool.health()[0].latency.is_some());
+    assert_eq!(pool.health()[0].in_flight, 0);
+}
+
+#[tokio::test]
+async fn test_with_api_concurrent() {
+    let pool = ApiPool::new(1).with_selection(Selection::LeastInFlight);
+    // request waits for connection, taken by with_api
+    let (res, api) = tokio::join!(
+        pool.with_api(|api| async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            (api + 1, api)
+        }),
+        async {
+            tokio::task::yield_now().await;
+            *pool.checkout().await.unwrap()
+        },
+    );
+    assert_eq!((res, api), (1, 2));
+    // with_api waits for request in flight
+    let pool = &pool;
+    let checkout = pool.checkout().await.unwrap();
+    let (res, _) = tokio::join!(
+        pool.with_api(|api| async move { (api, api) }),
+        async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            drop(checkout);
+        },
+    );
+    assert_eq!(res, 2);
+    assert_eq!(pool.health()[0].in_flight, 0);
+}
+
+#[tokio::test]
+async fn test_checkout() {
+    let pool = ApiPool::new(1).with_max_size(2).with_wait_timeout(Duration::from_millis(10));
+    pool.add(2);
+    assert_eq!(pool.try_add(3), Err(3));
+    assert_eq!(pool.size(), 2);
+    let first = pool.checkout().await.unwrap();
+    let second = pool.checkout().await.unwrap();
+    assert_eq!((*first, *second), (1, 2));
+    let err = pool.checkout().await.err().unwrap();
+    assert_eq!(err.code(), Code::ResourceExhausted);
+    drop(first);
+    assert_eq!(*pool.checkout().await.unwrap(), 1);
+}
@@ -0,0 +1,512 @@
+//! Recording of stream traffic into file and replay of recordings.
+//! File is a sequence of length-delimited protobuf [Record]s.
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{mpsc, Arc};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+use futures::channel::oneshot;
+use futures::SinkExt;
+use log::warn;
+use prost::Message;
+use tokio::sync::watch;
+
+use crate::stream::{StartStream, StreamControl, StreamEvent, StreamHandle, StreamMonitor};
+use crate::t_types::*;
+use crate::{Api, StreamResponse};
+
+/// Recorded request or response with time of receiving
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Record {
+    #[prost(message, optional, tag = "1")]
+    pub time: std::option::Option<prost_types::Timestamp>,
+    #[prost(oneof = "Payload", tags = "2, 3, 4, 5, 6, 7, 8, 9, 10, 11")]
+    pub payload: std::option::Option<Payload>,
+}
+
+/// Recorded message. Pings are not recorded by [Recorder], conversion of ping is empty [MarketDataResponse]
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum Payload {
+    #[prost(message, tag = "2")]
+    MarketDataRequest(MarketDataServerSideStreamRequest),
+    #[prost(message, tag = "3")]
+    TradesRequest(TradesStreamRequest),
+    #[prost(message, tag = "4")]
+    OrderStateRequest(OrderStateStreamRequest),
+    #[prost(message, tag = "5")]
+    PortfolioRequest(PortfolioStreamRequest),
+    #[prost(message, tag = "6")]
+    PositionsRequest(PositionsStreamRequest),
+    #[prost(message, tag = "7")]
+    MarketData(MarketDataResponse),
+    #[prost(message, tag = "8")]
+    Trades(TradesStreamResponse),
+    #[prost(message, tag = "9")]
+    OrderState(OrderStateStreamResponse),
+    #[prost(message, tag = "10")]
+    Portfolio(PortfolioStreamResponse),
+    #[prost(message, tag = "11")]
+    Positions(PositionsStreamResponse),
+}
+
+macro_rules! payload_from {
+    ($($variant:ident($t:ty),)+) => {$(
+        impl From<$t> for Payload {
+            fn from(value: $t) -> Self {
+                Self::$variant(value)
+            }
+        }
+    )+};
+}
+
+payload_from!(
+    MarketDataRequest(MarketDataServerSideStreamRequest),
+    TradesRequest(TradesStreamRequest),
+    OrderStateRequest(OrderStateStreamRequest),
+    PortfolioRequest(PortfolioStreamRequest),
+    PositionsRequest(PositionsStreamRequest),
+    MarketData(MarketDataResponse),
+    Trades(TradesStreamResponse),
+    OrderState(OrderStateStreamResponse),
+    Portfolio(PortfolioStreamResponse),
+    Positions(PositionsStreamResponse),
+);
+
+impl From<StreamResponse> for Payload {
+    fn from(value: StreamResponse) -> Self {
+        use market_data_response::Payload as M;
+        use trades_stream_response::Payload as T;
+        use order_state_stream_response::Payload as O;
+        use portfolio_stream_response::Payload as P;
+        use positions_stream_response::Payload as S;
+        let market = |x| MarketDataResponse { payload: Some(x) }.into();
+        match value {
+            StreamResponse::Ping => MarketDataResponse::default().into(),
+            StreamResponse::SubscribeCandlesResponse(x) => market(M::SubscribeCandlesResponse(x)),
+            StreamResponse::SubscribeOrderBookResponse(x) => market(M::SubscribeOrderBookResponse(x)),
+            StreamResponse::SubscribeTradesResponse(x) => market(M::SubscribeTradesResponse(x)),
+            StreamResponse::SubscribeInfoResponse(x) => market(M::SubscribeInfoResponse(x)),
+            StreamResponse::SubscribeLastPriceResponse(x) => market(M::SubscribeLastPriceResponse(x)),
+            StreamResponse::Candle(x) => market(M::Candle(x)),
+            StreamResponse::Trade(x) => market(M::Trade(x)),
+            StreamResponse::Orderbook(x) => market(M::Orderbook(x)),
+            StreamResponse::TradingStatus(x) => market(M::TradingStatus(x)),
+            StreamResponse::LastPrice(x) => market(M::LastPrice(x)),
+            StreamResponse::OrderTrades(x) => TradesStreamResponse { payload: Some(T::OrderTrades(x)) }.into(),
+            StreamResponse::TradesSubscription(x) => TradesStreamResponse { payload: Some(T::Subscription(x)) }.into(),
+            StreamResponse::OrderState(x) => OrderStateStreamResponse { payload: Some(O::OrderState(x)) }.into(),
+            StreamResponse::MyTradesSubscription(x) => OrderStateStreamResponse { payload: Some(O::Subscription(x)) }.into(),
+            StreamResponse::PortfolioResponse(x) => PortfolioStreamResponse { payload: Some(P::Portfolio(x)) }.into(),
+            StreamResponse::PortfolioSubscriptionResult(x) => PortfolioStreamResponse { payload: Some(P::Subscriptions(x)) }.into(),
+            StreamResponse::Position(x) => PositionsStreamResponse { payload: Some(S::Position(x)) }.into(),
+            StreamResponse::PositionsSubscriptions(x) => PositionsStreamResponse { payload: Some(S::Subscriptions(x)) }.into(),
+            StreamResponse::InitialPositions(x) => PositionsStreamResponse { payload: Some(S::InitialPositions(x)) }.into(),
+        }
+    }
+}
+
+impl Payload {
+    /// Response of stream, `None` for requests
+    pub fn response(self) -> std::option::Option<StreamResponse> {
+        Some(match self {
+            Self::MarketData(x) => x.into(),
+            Self::Trades(x) => x.into(),
+            Self::OrderState(x) => x.into(),
+            Self::Portfolio(x) => x.into(),
+            Self::Positions(x) => x.into(),
+            _ => return None,
+        })
+    }
+}
+
+/// Command of thread, that writes messages into file
+#[derive(Debug)]
+enum WriterCommand<M> {
+    Write(Box<M>),
+    /// Flush file and notify
+    Flush(oneshot::Sender<()>),
+}
+
+/// Writer of length-delimited messages into file by background thread, so async code is not blocked by disk.
+/// Errors of writing are logged. Clones write into the same file
+#[derive(Debug, Clone)]
+pub(crate) struct MessageWriter<M> {
+    commands: mpsc::Sender<WriterCommand<M>>,
+}
+
+impl<M: Message + Send + 'static> MessageWriter<M> {
+    pub(crate) fn new(file: File) -> Self {
+        let (commands, queue) = mpsc::channel();
+        let file = BufWriter::new(file);
+        std::thread::spawn(move || write_messages(file, queue));
+        Self { commands }
+    }
+    pub(crate) fn write(&self, message: M) -> std::io::Result<()> {
+        self.commands.send(WriterCommand::Write(Box::new(message)))
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "writer of recording is stopped"))
+    }
+    /// Notification, that all messages before are written and flushed. Notification of stopped writer is canceled
+    pub(crate) fn flush(&self) -> oneshot::Receiver<()> {
+        let (done, written) = oneshot::channel();
+        let _ = self.commands.send(WriterCommand::Flush(done));
+        written
+    }
+}
+
+/// Write messages until all senders are dropped. File is flushed, when there are no more messages in queue
+fn write_messages<M: Message>(mut file: BufWriter<File>, commands: mpsc::Receiver<WriterCommand<M>>) {
+    while let Ok(command) = commands.recv() {
+        let mut command = Some(command);
+        while let Some(next) = command {
+            match next {
+                WriterCommand::Write(message) => if let Err(e) = file.write_all(&message.encode_length_delimited_to_vec()) {
+                    warn!("cannot record message: {e}");
+                }
+                WriterCommand::Flush(done) => {
+                    if let Err(e) = file.flush() {
+                        warn!("cannot flush recording: {e}");
+                    }
+                    let _ = done.send(());
+                }
+            }
+            command = commands.try_recv().ok();
+        }
+        if let Err(e) = file.flush() {
+            warn!("cannot flush recording: {e}");
+        }
+    }
+}
+
+/// Sink adapter, that writes every response into file before sending it to inner sink.
+/// File is written by background thread, so the stream is not blocked by disk; closing of sink waits
+/// until all records are written. Errors of writing are logged, the stream is not interrupted by them.
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use yatis::record::Recorder;
+///     use t_types::*;
+/// #    let token = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
+///     let api = Api::create_invest_service(token).unwrap();
+///     let (s, r) = futures::channel::mpsc::channel::<StreamResponse>(10);
+///     let req = PortfolioStreamRequest { accounts: vec!["my-account".into()], ping_settings: None };
+///     let recorder = Recorder::create("portfolio.rec", s).unwrap();
+///     let handle = recorder.start_stream(&api, req).await.unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Recorder<S> {
+    inner: S,
+    writer: MessageWriter<Record>,
+    /// notification of written file on close of sink
+    written: std::option::Option<oneshot::Receiver<()>>,
+}
+
+impl<S> Recorder<S> {
+    /// Create file of recording (existing file is truncated)
+    pub fn create(path: impl AsRef<Path>, inner: S) -> std::io::Result<Self> {
+        Ok(Self::new(File::create(path)?, inner))
+    }
+    /// Append to existing file of recording
+    pub fn append(path: impl AsRef<Path>, inner: S) -> std::io::Result<Self> {
+        Ok(Self::new(File::options().create(true).append(true).open(path)?, inner))
+    }
+    fn new(file: File, inner: S) -> Self {
+        Self { inner, writer: MessageWriter::new(file), written: None }
+    }
+    /// Write request of stream. Requests of streams, started by [Recorder::start_stream], are written automatically
+    pub fn request(&self, req: impl Into<Payload>) -> std::io::Result<()> {
+        self.write(req.into())
+    }
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+    fn write(&self, payload: Payload) -> std::io::Result<()> {
+        write(&self.writer, payload)
+    }
+}
+
+fn write(writer: &MessageWriter<Record>, payload: Payload) -> std::io::Result<()> {
+    writer.write(Record { time: Some(SystemTime::now().into()), payload: Some(payload) })
+}
+
+impl<S> Recorder<S> where S: futures::Sink<StreamResponse> + Unpin + Send + 'static {
+    /// Start stream with recording of its responses, starting request and every change of request by [StreamHandle]
+    pub async fn start_stream<A, Req>(self, api: &A, req: Req) -> Result<StreamHandle<Req>, tonic::Status>
+    where A: StartStream<Req, StreamResponse>, Req: Into<Payload> + Clone + PartialEq + Send + Sync + 'static {
+        let writer = self.writer.clone();
+        if let Err(e) = self.request(req.clone()) {
+            warn!("cannot record stream request: {e}");
+        }
+        let handle = api.start_stream(req.clone(), self).await?;
+        let mut requests = handle.watch_request();
+        tokio::spawn(async move {
+            let mut last = req;
+            // finishes, when handle is dropped
+            while requests.changed().await.is_ok() {
+                let (req, control) = requests.borrow_and_update().clone();
+                if control == StreamControl::Stopped {
+                    break;
+                }
+                if req != last {
+                    if let Err(e) = write(&writer, req.clone().into()) {
+                        warn!("cannot record stream request: {e}");
+                    }
+                    last = req;
+                }
+            }
+        });
+        Ok(handle)
+    }
+}
+
+impl<S> futures::Sink<StreamResponse> for Recorder<S> where S: futures::Sink<StreamResponse> + Unpin {
+    type Error = S::Error;
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+    fn start_send(mut self: Pin<&mut Self>, item: StreamResponse) -> Result<(), Self::Error> {
+        // stream of ping is unknown, so it would be replayed as market data
+        if !matches!(item, StreamResponse::Ping) {
+            if let Err(e) = self.write(item.clone().into()) {
+                warn!("cannot record stream response: {e}");
+            }
+        }
+        Pin::new(&mut self.inner).start_send(item)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // file is flushed by writer, when its queue is empty
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.written.is_none() {
+            self.written = Some(self.writer.flush());
+        }
+        if let Some(written) = &mut self.written {
+            // canceled notification also means that writer is done
+            let _ = futures::ready!(Pin::new(written).poll(cx));
+        }
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// Read all length-delimited messages of file
+pub(crate) fn read_messages<M: Message + Default>(path: impl AsRef<Path>) -> std::io::Result<Vec<M>> {
+    let bytes = std::fs::read(path)?;
+    let mut buf = bytes.as_slice();
+    let mut messages = Vec::new();
+    while !buf.is_empty() {
+        let message = M::decode_length_delimited(&mut buf).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        messages.push(message);
+    }
+    Ok(messages)
+}
+
+/// Read all records of file
+pub fn read_records(path: impl AsRef<Path>) -> std::io::Result<Vec<Record>> {
+    read_messages(path)
+}
+
+/// Speed of replay
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ReplaySpeed {
+    /// with the same delays as recorded
+    #[default]
+    RealTime,
+    /// delays are divided by the factor
+    Accelerated(f64),
+    /// without delays
+    Unbounded,
+}
+
+impl ReplaySpeed {
+    fn delay(&self, from: &std::option::Option<prost_types::Timestamp>, to: &std::option::Option<prost_types::Timestamp>) -> Duration {
+        let (Some(from), Some(to)) = (from, to) else { return Duration::ZERO };
+        let delay = SystemTime::try_from(*to).ok()
+            .and_then(|to| to.duration_since(SystemTime::try_from(*from).ok()?).ok())
+            .unwrap_or_default();
+        match self {
+            Self::RealTime => delay,
+            Self::Accelerated(factor) if *factor > 0.0 => delay.div_f64(*factor),
+            Self::Accelerated(_) | Self::Unbounded => Duration::ZERO,
+        }
+    }
+}
+
+/// Wait for delay, while stream is running. Returns `false`, if stream is stopped
+async fn wait<Req>(commands: &mut watch::Receiver<(Req, StreamControl)>, delay: Duration) -> bool {
+    let deadline = tokio::time::Instant::now() + delay;
+    loop {
+        let state = commands.borrow_and_update().1;
+        match state {
+            StreamControl::Stopped => return false,
+            StreamControl::Paused => if commands.changed().await.is_err() {
+                // handle is dropped, nobody can resume
+                return true;
+            }
+            StreamControl::Running => tokio::select! {
+                _ = tokio::time::sleep_until(deadline) => return true,
+                changed = commands.changed() => if changed.is_err() {
+                    tokio::time::sleep_until(deadline).await;
+                    return true;
+                }
+            }
+        }
+    }
+}
+
+/// Replay of recorded responses. Stream plays responses of the same kind as request (e.g. [MarketDataResponse]s
+/// for [MarketDataServerSideStreamRequest]), the content of request is ignored.
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use yatis::record::{Replay, ReplaySpeed};
+///     use t_types::*;
+/// #   let path = std::env::temp_dir().join("yatis-replay-doc.rec");
+/// #   yatis::record::Recorder::create(&path, futures::sink::drain::<StreamResponse>()).unwrap();
+///     let replay = Replay::open(&path).unwrap().with_speed(ReplaySpeed::Accelerated(10.0));
+///     let (s, r) = futures::channel::mpsc::channel::<StreamResponse>(10);
+///     let handle = replay.start_stream(MarketDataServerSideStreamRequest::default(), s).await.unwrap();
+///     handle.join().await.unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Replay {
+    records: Arc<Vec<Record>>,
+    speed: ReplaySpeed,
+}
+
+impl Replay {
+    /// Replay of file, recorded by [Recorder]
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self::new(read_records(path)?))
+    }
+    pub fn new(records: Vec<Record>) -> Self {
+        Self { records: Arc::new(records), speed: ReplaySpeed::default() }
+    }
+    pub fn with_speed(self, speed: ReplaySpeed) -> Self {
+        Self { speed, ..self }
+    }
+    /// All records, including requests
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+    fn play<Req, T, S>(&self, req: Req, mut sender: S, convert: fn(&Payload) -> std::option::Option<T>) -> StreamHandle<Req>
+    where Req: Clone + Send + Sync + 'static, T: Send + 'static, S: futures::Sink<T> + Unpin + Send + 'static {
+        let (reporter, monitor) = StreamMonitor::new();
+        let (control, mut commands) = watch::channel((req, StreamControl::Running));
+        let records = self.records.clone();
+        let speed = self.speed;
+        reporter.event(StreamEvent::Connected);
+        let handle = tokio::spawn(async move {
+            let mut prev = None;
+            for record in records.iter() {
+                let Some(item) = record.payload.as_ref().and_then(convert) else { continue };
+                let delay = prev.map_or(Duration::ZERO, |prev| speed.delay(&prev, &record.time));
+                prev = Some(record.time);
+                if !wait(&mut commands, delay).await {
+                    reporter.event(StreamEvent::Stopped("stopped by handle".into()));
+                    return;
+                }
+                reporter.message();
+                if sender.send(item).await.is_err() {
+                    reporter.event(StreamEvent::Stopped("receiver is closed".into()));
+                    return;
+                }
+            }
+            reporter.event(StreamEvent::Stopped("replay is finished".into()));
+        });
+        StreamHandle::new(control, monitor, handle)
+    }
+}
+
+macro_rules! replay_impl {
+    ($($req:ty => $res:ident($variant:ident),)+) => {$(
+        impl<T> StartStream<$req, T> for Replay where T: From<$res> + Send + 'static {
+            fn start_stream<S>(&self, req: $req, sender: S) -> impl Future<Output = Result<StreamHandle<$req>, tonic::Status>> + Send
+            where S: futures::Sink<T> + Unpin + Send + 'static {
+                let handle = self.play(req, sender, |payload| match payload {
+                    Payload::$variant(x) => Some(x.clone().into()),
+                    _ => None,
+                });
+                async move { Ok(handle) }
+            }
+            fn stream_name() -> &'static str {
+                <Api as StartStream<$req, StreamResponse>>::stream_name()
+            }
+        }
+    )+};
+}
+
+replay_impl!(
+    MarketDataServerSideStreamRequest => MarketDataResponse(MarketData),
+    TradesStreamRequest => TradesStreamResponse(Trades),
+    OrderStateStreamRequest => OrderStateStreamResponse(OrderState),
+    PortfolioStreamRequest => PortfolioStreamResponse(Portfolio),
+    PositionsStreamRequest => PositionsStreamResponse(Positions),
+);
+
+#[tokio::test]
+async fn test_record_replay() {
+    use futures::StreamExt;
+    let path = std::env::temp_dir().join(format!("yatis-test-record-{}.rec", uuid::Uuid::new_v4()));
+    let (s, r) = futures::channel::mpsc::channel::<StreamResponse>(10);
+    let mut recorder = Recorder::create(&path, s).unwrap();
+    let req = MarketDataServerSideStreamRequest::default();
+    recorder.request(req.clone()).unwrap();
+    let price = |units| StreamResponse::LastPrice(LastPrice { figi: "f".into(), price: Some(Quotation { units, nano: 0 }), ..Default::default() });
+    let order = StreamResponse::OrderState(Default::default());
+    recorder.send(price(1)).await.unwrap();
+    recorder.send(StreamResponse::Ping).await.unwrap();
+    recorder.send(order).await.unwrap();
+    recorder.send(price(2)).await.unwrap();
+    recorder.close().await.unwrap();
+    assert_eq!(r.count().await, 4);
+
+    let replay = Replay::open(&path).unwrap().with_speed(ReplaySpeed::Unbounded);
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(replay.records().len(), 4);
+    assert_eq!(replay.records()[0].payload, Some(Payload::MarketDataRequest(req.clone())));
+    let (s, r) = futures::channel::mpsc::channel::<StreamResponse>(10);
+    let handle = replay.start_stream(req, s).await.unwrap();
+    handle.join().await.unwrap();
+    let prices: Vec<_> = r.filter_map(|x| async move {
+        match x {
+            StreamResponse::LastPrice(p) => p.price.map(|p| p.units),
+            _ => None,
+        }
+    }).collect().await;
+    assert_eq!(prices, vec![1, 2]);
+    let from = Some(prost_types::Timestamp { seconds: 10, nanos: 0 });
+    let to = Some(prost_types::Timestamp { seconds: 12, nanos: 0 });
+    assert_eq!(ReplaySpeed::Accelerated(4.0).delay(&from, &to), Duration::from_millis(500));
+}
+
+#[tokio::test]
+async fn test_record_requests() {
+    let path = std::env::temp_dir().join(format!("yatis-test-record-{}.rec", uuid::Uuid::new_v4()));
+    let api = crate::paper::PaperApi::new();
+    let recorder = Recorder::create(&path, futures::sink::drain()).unwrap();
+    let req = MarketDataServerSideStreamRequest::default();
+    let handle = recorder.start_stream(&api, req.clone()).await.unwrap();
+    let changed = MarketDataServerSideStreamRequest { subscribe_last_price_request: Some(Default::default()), ..Default::default() };
+    handle.set_request(changed.clone());
+    let mut requests = Vec::new();
+    for _ in 0..100 {
+        requests = read_records(&path).unwrap().into_iter().filter_map(|r| r.payload).collect();
+        if requests.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    handle.stop();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(requests, vec![Payload::MarketDataRequest(req), Payload::MarketDataRequest(changed)]);
+}
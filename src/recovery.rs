@@ -0,0 +1,191 @@
+//! Recovery of order states after reconnect of order state stream. Order changes, missed while stream was disconnected,
+//! are requested by [GetOrdersRequest] and [GetOrderStateRequest] and sent as synthetic [StreamResponse::OrderState].
+//!
+//! Only orders, known before disconnect or still active after it, are recovered. Orders, that are posted and completed
+//! while stream was disconnected, are not returned by API, their executions can be found by [OperationsRequest]
+//! from the time of last message before disconnect (see [crate::stream::StreamMonitor::last_message]).
+use std::collections::{HashMap, HashSet};
+
+use futures::{SinkExt, StreamExt};
+use log::{info, warn};
+
+use crate::requestor::OwnedSender;
+use crate::stream::{StartStream, StreamEvent, StreamHandle};
+use crate::stream_response::OrderStateResponse;
+use crate::t_types::*;
+use crate::StreamResponse;
+
+fn is_final(status: i32) -> bool {
+    use OrderExecutionReportStatus::*;
+    [ExecutionReportStatusFill, ExecutionReportStatusRejected, ExecutionReportStatusCancelled].into_iter().any(|s| s as i32 == status)
+}
+
+/// Stream-like state of order, built from unary [OrderState]
+fn order_state_response(account_id: &str, state: OrderState) -> OrderStateResponse {
+    let lots_left = if is_final(state.execution_report_status) { 0 } else { state.lots_requested - state.lots_executed };
+    let lots_cancelled = if state.execution_report_status == OrderExecutionReportStatus::ExecutionReportStatusCancelled as i32 {
+        state.lots_requested - state.lots_executed
+    } else { 0 };
+    OrderStateResponse {
+        order_id: state.order_id,
+        order_request_id: Some(state.order_request_id).filter(|id| !id.is_empty()),
+        created_at: state.order_date,
+        execution_report_status: state.execution_report_status,
+        direction: state.direction,
+        order_type: state.order_type,
+        account_id: account_id.to_owned(),
+        initial_order_price: state.initial_order_price,
+        order_price: state.initial_security_price,
+        amount: state.total_order_amount,
+        executed_order_price: state.executed_order_price,
+        currency: state.currency,
+        lots_requested: state.lots_requested,
+        lots_executed: state.lots_executed,
+        lots_left,
+        lots_cancelled,
+        trades: state.stages.into_iter().map(|s| OrderTrade {
+            date_time: s.execution_time,
+            price: s.price.map(Into::into),
+            quantity: s.quantity,
+            trade_id: s.trade_id,
+        }).collect(),
+        instrument_uid: state.instrument_uid,
+        ..Default::default()
+    }
+}
+
+/// Last known states of active orders
+#[derive(Debug, Default)]
+struct OrderTracker {
+    /// order id -> (account, status, executed lots)
+    orders: HashMap<String, (String, i32, i64)>,
+}
+
+impl OrderTracker {
+    /// Remember state and return `true`, if it differs from known state
+    fn update(&mut self, account_id: &str, order_id: &str, status: i32, lots_executed: i64) -> bool {
+        let known = self.orders.get(order_id).map(|(_, s, l)| (*s, *l));
+        if is_final(status) {
+            self.orders.remove(order_id);
+        } else {
+            self.orders.insert(order_id.to_owned(), (account_id.to_owned(), status, lots_executed));
+        }
+        known != Some((status, lots_executed))
+    }
+    fn track(&mut self, state: &OrderStateResponse) -> bool {
+        self.update(&state.account_id, &state.order_id, state.execution_report_status, state.lots_executed)
+    }
+    /// Known active orders of account, that are absent in `active`
+    fn missing(&self, account_id: &str, active: &HashSet<String>) -> Vec<String> {
+        self.orders.iter()
+            .filter(|(id, (account, _, _))| account == account_id && !active.contains(*id))
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+}
+
+/// Actual states of orders, that are changed since last known states
+async fn recover<T>(api: &T, tracker: &mut OrderTracker, accounts: Vec<String>) -> Result<Vec<OrderStateResponse>, tonic::Status>
+where T: OwnedSender<GetOrdersRequest, GetOrdersResponse> + OwnedSender<GetOrderStateRequest, OrderState>
+    + OwnedSender<GetAccountsRequest, GetAccountsResponse> {
+    let accounts = if accounts.is_empty() {
+        api.send(GetAccountsRequest::default()).await?.accounts.into_iter().map(|a| a.id).collect()
+    } else { accounts };
+    let mut changed = Vec::new();
+    for account_id in accounts {
+        let orders = api.send(GetOrdersRequest { account_id: account_id.clone() }).await?.orders;
+        let active: HashSet<_> = orders.iter().map(|o| o.order_id.clone()).collect();
+        let mut states = orders;
+        for order_id in tracker.missing(&account_id, &active) {
+            states.push(api.send(GetOrderStateRequest { account_id: account_id.clone(), order_id, ..Default::default() }).await?);
+        }
+        for state in states {
+            if tracker.update(&account_id, &state.order_id, state.execution_report_status, state.lots_executed) {
+                changed.push(order_state_response(&account_id, state));
+            }
+        }
+    }
+    Ok(changed)
+}
+
+/// Open order state stream, which recovers missed order changes after every reconnect.
+/// Changes are sent to `sender` as [StreamResponse::OrderState] before further live updates.
+/// Orders, posted and completed during disconnect, are not recovered, see [module docs](self).
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use yatis::recovery::start_order_stream;
+///     use t_types::*;
+/// #    let token = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
+///     let api = Api::create_invest_service(token).unwrap();
+///     let (s, mut r) = futures::channel::mpsc::channel::<StreamResponse>(10);
+///     let _handle = start_order_stream(api, OrderStateStreamRequest::default(), s).await.unwrap();
+///     use futures::StreamExt;
+///     println!("{:?}", r.next().await);
+/// # }
+/// ```
+pub async fn start_order_stream<T, S>(api: T, req: OrderStateStreamRequest, mut sender: S) -> Result<StreamHandle<OrderStateStreamRequest>, tonic::Status>
+where
+    T: StartStream<OrderStateStreamRequest, StreamResponse> + OwnedSender<GetOrdersRequest, GetOrdersResponse>
+        + OwnedSender<GetOrderStateRequest, OrderState> + OwnedSender<GetAccountsRequest, GetAccountsResponse> + Send + Sync + 'static,
+    S: futures::Sink<StreamResponse> + Unpin + Send + 'static,
+{
+    let (s, mut r) = futures::channel::mpsc::channel::<StreamResponse>(16);
+    let handle = api.start_stream(req, s).await?;
+    let request = handle.watch_request();
+    let mut events = handle.monitor().subscribe();
+    tokio::spawn(async move {
+        let mut tracker = OrderTracker::default();
+        loop {
+            let response = tokio::select! {
+                response = r.next() => match response {
+                    Some(response) => response,
+                    None => break,
+                },
+                changed = events.changed() => {
+                    if changed.is_err() { break }
+                    if *events.borrow_and_update() != StreamEvent::Resubscribed { continue }
+                    let accounts = request.borrow().0.accounts.clone();
+                    match recover(&api, &mut tracker, accounts).await {
+                        Ok(states) => {
+                            info!("{} order states recovered after reconnect", states.len());
+                            for state in states {
+                                if sender.send(StreamResponse::OrderState(state)).await.is_err() { return }
+                            }
+                        }
+                        Err(e) => warn!("cannot recover order states: {e:?}"),
+                    }
+                    continue;
+                }
+            };
+            if let StreamResponse::OrderState(state) = &response {
+                tracker.track(state);
+            }
+            if sender.send(response).await.is_err() { break }
+        }
+    });
+    Ok(handle)
+}
+
+#[test]
+fn test_order_tracker() {
+    use OrderExecutionReportStatus::*;
+    let mut tracker = OrderTracker::default();
+    let state = |order_id: &str, status: OrderExecutionReportStatus, lots_executed| OrderStateResponse {
+        order_id: order_id.into(), account_id: "acc".into(), execution_report_status: status as i32, lots_executed, ..Default::default()
+    };
+    assert!(tracker.track(&state("a", ExecutionReportStatusNew, 0)));
+    assert!(tracker.track(&state("b", ExecutionReportStatusNew, 0)));
+    assert!(!tracker.update("acc", "a", ExecutionReportStatusNew as i32, 0));
+    assert!(tracker.update("acc", "a", ExecutionReportStatusPartiallyfill as i32, 2));
+    assert_eq!(tracker.missing("acc", &["a".to_string()].into()), vec!["b".to_string()]);
+    assert!(tracker.missing("other", &HashSet::new()).is_empty());
+    let filled = order_state_response("acc", OrderState {
+        order_id: "b".into(), execution_report_status: ExecutionReportStatusFill as i32, lots_requested: 3, lots_executed: 3, ..Default::default()
+    });
+    assert!(tracker.track(&filled));
+    assert_eq!(filled.lots_left, 0);
+    assert_eq!(tracker.missing("acc", &HashSet::new()), vec!["a".to_string()]);
+}
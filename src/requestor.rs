@@ -1,17 +1,86 @@
 //! Traits and implementations for unary requests to API. Target is implement single method [Requestor::request] for all Requests and Responses.
 use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tonic::metadata::MetadataMap;
+
 use crate::Api;
 
 /// Auto implemented trait to send unary requests to grpc. Used for best type derivation. Uses [OwnedSender] implementation.
 pub trait Requestor<Req, Res> where Self: Sized {
     /// send unary request to grpc and process response
     fn request(&self, req: Req) -> impl Future<Output = Result<Res, tonic::Status>> + Send;
+    /// the same as [Requestor::request], but returns response with metadata
+    fn request_with_meta(&self, req: Req) -> impl Future<Output = Result<Response<Res>, tonic::Status>> + Send;
 }
 
 impl<Api, Req, Res> Requestor<Req, Res> for Api where Api: OwnedSender<Req, Res>, Req: Send, Res: Send {
     fn request(&self, req: Req) -> impl Future<Output = Result<Res, tonic::Status>>  + Send {
         self.send(req)
     }
+    fn request_with_meta(&self, req: Req) -> impl Future<Output = Result<Response<Res>, tonic::Status>> + Send {
+        self.send_with_meta(req)
+    }
+}
+
+/// Response of unary request with metadata
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use t_types::*;
+/// #    let token = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
+///     let api = Api::create_invest_service(token).unwrap();
+///     let res = api.request_with_meta(GetInfoRequest{}).await.unwrap();
+///     println!("{:?} in {:?}, tracking id: {:?}", res.message, res.latency, res.tracking_id);
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Response<Res> {
+    pub message: Res,
+    /// `x-tracking-id` of response, or of request when server doesn't send it. Needed for support requests
+    pub tracking_id: std::option::Option<String>,
+    pub headers: MetadataMap,
+    /// round-trip time of request
+    pub latency: Duration,
+}
+
+impl<Res> Response<Res> {
+    pub fn new(res: tonic::Response<Res>, latency: Duration) -> Self {
+        let (headers, message, _) = res.into_parts();
+        let tracking_id = headers.get("x-tracking-id").and_then(|v| v.to_str().ok()).map(ToOwned::to_owned);
+        Self { message, tracking_id, headers, latency }
+    }
+    pub fn into_inner(self) -> Res {
+        self.message
+    }
+    /// Use tracking id of request, if response has no tracking id
+    pub(crate) fn with_sent_tracking_id(mut self, sent: SentTrackingId) -> Self {
+        if self.tracking_id.is_none() {
+            self.tracking_id = sent.0.lock().unwrap().take();
+        }
+        self
+    }
+}
+
+/// Slot in extensions of request, where [crate::TokenInterceptor] puts sent `x-tracking-id`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SentTrackingId(Arc<Mutex<std::option::Option<String>>>);
+
+impl SentTrackingId {
+    pub(crate) fn set(&self, tracking_id: &str) {
+        *self.0.lock().unwrap() = Some(tracking_id.to_owned());
+    }
+}
+
+/// Request with slot for sent tracking id
+pub(crate) fn tracked<Req>(req: Req) -> (tonic::Request<Req>, SentTrackingId) {
+    let sent = SentTrackingId::default();
+    let mut req = tonic::Request::new(req);
+    req.extensions_mut().insert(sent.clone());
+    (req, sent)
 }
 
 /// Main trait for unary requests.
@@ -20,6 +89,20 @@ pub trait OwnedSender<Req, Res> where Self: Sized {
     fn send_and_back(self, req: Req) -> impl Future<Output = (Self,Result<Res, tonic::Status>)> + Send;
     /// just execute request, using &self. In most implementatinos it clones self
     fn send(&self, req: Req) -> impl Future<Output = Result<Res, tonic::Status>> + Send;
+    /// full grpc name of method, e.g. `tinkoff.public.invest.api.contract.v1.UsersService/GetInfo`. Used for limits,
+    /// so wrappers must return name of inner method. Default empty name means no limit of method
+    fn method_name() -> &'static str {
+        ""
+    }
+    /// execute request and return response with metadata. Default implementation has only latency
+    fn send_with_meta(&self, req: Req) -> impl Future<Output = Result<Response<Res>, tonic::Status>> + Send {
+        let start = Instant::now();
+        let fut = self.send(req);
+        async move {
+            let message = fut.await?;
+            Ok(Response { message, tracking_id: None, headers: MetadataMap::new(), latency: start.elapsed() })
+        }
+    }
 }
 
 macro_rules! sender_impl {
@@ -38,6 +121,17 @@ macro_rules! sender_impl {
                 let r = client.$method(req).await.map(|r|r.into_inner());
                 r
             })}
+            fn send_with_meta(&self, req: $req) -> impl Future<Output = Result<Response<$res>, tonic::Status>> {Box::pin(async move {
+                let mut client = $client::from(self.clone());
+                let start = Instant::now();
+                let (req, sent) = tracked(req);
+                let r = client.$method(req).await?;
+                Ok(Response::new(r, start.elapsed()).with_sent_tracking_id(sent))
+            })}
+            fn method_name() -> &'static str {
+                static NAME: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+                NAME.get_or_init(||crate::limits::grpc_method(stringify!($client), stringify!($method)))
+            }
         }
     )+}
 }
@@ -0,0 +1,321 @@
+//! Retries of unary requests, that are safe to repeat
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tonic::Code;
+
+use crate::requestor::{AnyRequestor, OwnedSender, Response};
+use crate::stream::{AnyStream, ReconnectPolicy, StartStream, StreamHandle};
+use crate::t_types::*;
+use crate::StreamResponse;
+
+/// Requests, that can be repeated without side effects
+pub trait Idempotent {
+    fn is_idempotent(&self) -> bool;
+}
+
+macro_rules! idempotent_impl {
+    ($value:literal: $($req:ty),+ $(,)?) => {$(
+        impl Idempotent for $req {
+            fn is_idempotent(&self) -> bool { $value }
+        }
+    )+}
+}
+
+idempotent_impl!(true:
+    AssetRequest, AssetsRequest, FilterOptionsRequest, FindInstrumentRequest, GetAccountsRequest,
+    GetAccruedInterestsRequest, GetAssetFundamentalsRequest, GetAssetReportsRequest, GetBondCouponsRequest,
+    GetBondEventsRequest, GetBrandRequest, GetBrandsRequest, GetCandlesRequest, GetClosePricesRequest,
+    GetConsensusForecastsRequest, GetCountriesRequest, GetDividendsForeignIssuerRequest, GetDividendsRequest,
+    GetFavoriteGroupsRequest, GetFavoritesRequest, GetForecastRequest, GetFuturesMarginRequest, GetInfoRequest,
+    GetLastPricesRequest, GetLastTradesRequest, GetMarginAttributesRequest, GetMaxLotsRequest,
+    GetOperationsByCursorRequest, GetOrderBookRequest, GetOrderPriceRequest, GetOrderStateRequest, GetOrdersRequest,
+    GetSignalsRequest, GetStopOrdersRequest, GetStrategiesRequest, GetTechAnalysisRequest, GetTradingStatusRequest,
+    GetTradingStatusesRequest, GetUserTariffRequest, IndicativesRequest, InstrumentRequest, InstrumentsRequest,
+    OperationsRequest, PortfolioRequest, PositionsRequest, RiskRatesRequest, TradingSchedulesRequest,
+    WithdrawLimitsRequest,
+);
+
+idempotent_impl!(false:
+    CancelOrderRequest, CancelStopOrderRequest, CreateFavoriteGroupRequest, DeleteFavoriteGroupRequest,
+    EditFavoritesRequest, OpenSandboxAccountRequest, CloseSandboxAccountRequest, SandboxPayInRequest,
+);
+
+// orders are idempotent only with idempotency key
+impl Idempotent for PostOrderRequest {
+    fn is_idempotent(&self) -> bool { !self.order_id.is_empty() }
+}
+impl Idempotent for PostOrderAsyncRequest {
+    fn is_idempotent(&self) -> bool { !self.order_id.is_empty() }
+}
+impl Idempotent for PostStopOrderRequest {
+    fn is_idempotent(&self) -> bool { !self.order_id.is_empty() }
+}
+impl Idempotent for ReplaceOrderRequest {
+    fn is_idempotent(&self) -> bool { !self.idempotency_key.is_empty() }
+}
+
+/// When and how often to retry failed requests
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    codes: Vec<Code>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.0,
+            codes: vec![Code::Unavailable, Code::Internal, Code::DeadlineExceeded],
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Attempts including first one
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+    /// Status codes to retry
+    pub fn with_codes(mut self, codes: impl IntoIterator<Item = Code>) -> Self {
+        self.codes = codes.into_iter().collect();
+        self
+    }
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+    pub fn is_retryable(&self, status: &tonic::Status) -> bool {
+        self.codes.contains(&status.code())
+    }
+    /// Exponential backoff after failed attempt (starts from 1), without jitter
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        exponential(self.initial_backoff, self.max_backoff, self.multiplier, attempt)
+    }
+    /// Backoff with random jitter, from half to full of [RetryPolicy::backoff]
+    pub fn jittered_backoff(&self, attempt: u32) -> Duration {
+        jitter(self.backoff(attempt))
+    }
+}
+
+/// Delay before `attempt` (from 1): `initial` multiplied by `multiplier` for every next attempt, not more than `max`
+pub(crate) fn exponential(initial: Duration, max: Duration, multiplier: f64, attempt: u32) -> Duration {
+    let factor = multiplier.powi(attempt.saturating_sub(1) as i32);
+    initial.mul_f64(factor).min(max)
+}
+
+/// Random duration from half to full of `delay`
+pub(crate) fn jitter(delay: Duration) -> Duration {
+    delay / 2 + (delay / 2).mul_f64(fastrand::f64())
+}
+
+/// Counters of requests through [Retrying]
+#[derive(Debug, Default)]
+pub struct RetryStats {
+    requests: AtomicU64,
+    attempts: AtomicU64,
+    exhausted: AtomicU64,
+}
+
+impl RetryStats {
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+    /// All attempts, including first ones
+    pub fn attempts(&self) -> u64 {
+        self.attempts.load(Ordering::Relaxed)
+    }
+    pub fn retries(&self) -> u64 {
+        self.attempts().saturating_sub(self.requests())
+    }
+    /// Requests, failed after all attempts
+    pub fn exhausted(&self) -> u64 {
+        self.exhausted.load(Ordering::Relaxed)
+    }
+}
+
+/// Wrapper for api, that retries idempotent requests on transient errors
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use yatis::retry::{RetryPolicy, Retrying};
+/// #    let token = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
+///     let api = Api::create_invest_service(token).unwrap();
+///     let api = Retrying::new(api, RetryPolicy::new().with_max_attempts(5));
+///     trading(api.clone()).await;
+///     println!("retries: {}", api.stats().retries());
+/// # }
+/// async fn trading(api: impl yatis::InvestApi) {
+///     use yatis::*;
+///     println!("{:?}", api.request(t_types::GetInfoRequest{}).await);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Retrying<T> {
+    inner: T,
+    policy: Arc<RetryPolicy>,
+    stats: Arc<RetryStats>,
+}
+
+impl<T> Retrying<T> {
+    pub fn new(inner: T, policy: RetryPolicy) -> Self {
+        Self { inner, policy: Arc::new(policy), stats: Default::default() }
+    }
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+    pub fn stats(&self) -> &RetryStats {
+        &self.stats
+    }
+    /// Decide to retry after failed attempt, and wait backoff if so
+    async fn retry(&self, method: &str, attempt: u32, status: &tonic::Status) -> bool {
+        if !self.policy.is_retryable(status) {
+            return false;
+        }
+        if attempt >= self.policy.max_attempts {
+            self.stats.exhausted.fetch_add(1, Ordering::Relaxed);
+            log::warn!("{method} failed after {attempt} attempts: {status}");
+            return false;
+        }
+        let backoff = self.policy.jittered_backoff(attempt);
+        log::warn!("{method} failed on attempt {attempt}: {status}, retry after {backoff:?}");
+        tokio::time::sleep(backoff).await;
+        self.stats.attempts.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+    /// Send request by `send`, repeating it while retry is allowed
+    async fn send_retrying<Req, R, F, Fut>(&self, method: &str, req: Req, send: F) -> Result<R, tonic::Status>
+    where Req: Idempotent + Clone, F: Fn(Req) -> Fut, Fut: Future<Output = Result<R, tonic::Status>> {
+        self.stats.requests.fetch_add(1, Ordering::Relaxed);
+        self.stats.attempts.fetch_add(1, Ordering::Relaxed);
+        if !req.is_idempotent() {
+            return send(req).await;
+        }
+        let mut attempt = 1;
+        loop {
+            match send(req.clone()).await {
+                Err(status) if self.retry(method, attempt, &status).await => attempt += 1,
+                res => return res,
+            }
+        }
+    }
+}
+
+impl<T, Req, Res> OwnedSender<Req, Res> for Retrying<T>
+where T: OwnedSender<Req, Res> + Send + Sync, Req: Idempotent + Clone + Send, Res: Send {
+    fn send_and_back(self, req: Req) -> impl Future<Output = (Self, Result<Res, tonic::Status>)> + Send {
+        Box::pin(async move {
+            self.stats.requests.fetch_add(1, Ordering::Relaxed);
+            self.stats.attempts.fetch_add(1, Ordering::Relaxed);
+            let mut this = self;
+            let idempotent = req.is_idempotent();
+            let mut attempt = 1;
+            loop {
+                let Self { inner, policy, stats } = this;
+                let (inner, res) = inner.send_and_back(req.clone()).await;
+                this = Self { inner, policy, stats };
+                match res {
+                    Err(status) if idempotent && this.retry(T::method_name(), attempt, &status).await => attempt += 1,
+                    res => return (this, res),
+                }
+            }
+        })
+    }
+    fn send(&self, req: Req) -> impl Future<Output = Result<Res, tonic::Status>> + Send {
+        Box::pin(self.send_retrying(T::method_name(), req, |req| self.inner.send(req)))
+    }
+    fn send_with_meta(&self, req: Req) -> impl Future<Output = Result<Response<Res>, tonic::Status>> + Send {
+        Box::pin(self.send_retrying(T::method_name(), req, |req| self.inner.send_with_meta(req)))
+    }
+    fn method_name() -> &'static str {
+        T::method_name()
+    }
+}
+
+impl<T, Req, X> StartStream<Req, X> for Retrying<T> where T: StartStream<Req, X> + Sync, Req: Send {
+    fn start_stream<S>(&self, req: Req, sender: S) -> impl Future<Output = Result<StreamHandle<Req>, tonic::Status>> + Send
+    where S: futures::Sink<X> + Unpin + Send + 'static {
+        self.inner.start_stream(req, sender)
+    }
+    fn start_stream_with_policy<S>(&self, req: Req, sender: S, policy: ReconnectPolicy) -> impl Future<Output = Result<StreamHandle<Req>, tonic::Status>> + Send
+    where S: futures::Sink<X> + Unpin + Send + 'static {
+        self.inner.start_stream_with_policy(req, sender, policy)
+    }
+    fn stream_name() -> &'static str {
+        T::stream_name()
+    }
+}
+
+impl<T: AnyRequestor + Sync> AnyRequestor for Retrying<T> {}
+impl<T: AnyStream<StreamResponse> + Sync> AnyStream<StreamResponse> for Retrying<T> {}
+
+#[test]
+fn test_backoff() {
+    let policy = RetryPolicy::new().with_backoff(Duration::from_millis(100), Duration::from_secs(1));
+    assert_eq!(policy.backoff(1), Duration::from_millis(100));
+    assert_eq!(policy.backoff(2), Duration::from_millis(200));
+    assert_eq!(policy.backoff(10), Duration::from_secs(1));
+    let jittered = policy.jittered_backoff(3);
+    assert!(jittered >= Duration::from_millis(200) && jittered <= Duration::from_millis(400));
+    assert!(policy.is_retryable(&tonic::Status::unavailable("")));
+    assert!(!policy.is_retryable(&tonic::Status::invalid_argument("")));
+    assert!(GetCandlesRequest::default().is_idempotent());
+    assert!(!PostOrderRequest::default().is_idempotent());
+    assert!(PostOrderRequest { order_id: "key".into(), ..Default::default() }.is_idempotent());
+}
+
+#[tokio::test]
+async fn test_exhausted() {
+    let api = Retrying::new((), RetryPolicy::new().with_max_attempts(2).with_backoff(Duration::ZERO, Duration::ZERO));
+    assert!(api.retry("method", 1, &tonic::Status::unavailable("")).await);
+    assert!(!api.retry("method", 2, &tonic::Status::invalid_argument("")).await);
+    assert_eq!(api.stats().exhausted(), 0);
+    assert!(!api.retry("method", 2, &tonic::Status::unavailable("")).await);
+    assert_eq!(api.stats().exhausted(), 1);
+}
+
+#[cfg(feature = "mock")]
+#[tokio::test]
+#[allow(clippy::result_large_err)]
+async fn test_retry_with_meta() {
+    use std::sync::atomic::AtomicU32;
+    use crate::Requestor;
+    let mock = crate::mock::MockServer::start().await.unwrap();
+    let calls = Arc::new(AtomicU32::new(0));
+    let counter = calls.clone();
+    mock.on(move |_: GetInfoRequest| match counter.fetch_add(1, Ordering::Relaxed) {
+        0 => Err(tonic::Status::unavailable("down")),
+        _ => Ok(GetInfoResponse { user_id: "user".into(), ..Default::default() }),
+    });
+    let api = Retrying::new(mock.api(), RetryPolicy::new().with_backoff(Duration::ZERO, Duration::ZERO));
+    let res = api.request_with_meta(GetInfoRequest {}).await.unwrap();
+    assert_eq!(res.message.user_id, "user");
+    assert!(res.tracking_id.is_some());
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+    assert_eq!(api.stats().retries(), 1);
+}
@@ -0,0 +1,237 @@
+//! Routing of requests between apis with different tokens by account
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+use crate::requestor::{AnyRequestor, OwnedSender, Response};
+use crate::stream::{AnyStream, ReconnectPolicy, StartStream, StreamHandle};
+use crate::t_types::*;
+use crate::StreamResponse;
+
+/// Requests, that are bound to account
+pub trait AccountRouted {
+    /// Account of request, `None` for requests without account (can be sent with any token)
+    fn account(&self) -> std::option::Option<&str>;
+    /// All accounts of request. Streams can have several accounts
+    fn accounts(&self) -> Vec<&str> {
+        self.account().into_iter().collect()
+    }
+}
+
+macro_rules! account_routed_impl {
+    (account_id: $($req:ty),+ $(,)?) => {$(
+        impl AccountRouted for $req {
+            fn account(&self) -> std::option::Option<&str> {
+                Some(self.account_id.as_str()).filter(|a| !a.is_empty())
+            }
+        }
+    )+};
+    (accounts: $($req:ty),+ $(,)?) => {$(
+        impl AccountRouted for $req {
+            fn account(&self) -> std::option::Option<&str> {
+                self.accounts.first().map(String::as_str)
+            }
+            fn accounts(&self) -> Vec<&str> {
+                self.accounts.iter().map(String::as_str).collect()
+            }
+        }
+    )+};
+    (none: $($req:ty),+ $(,)?) => {$(
+        impl AccountRouted for $req {
+            fn account(&self) -> std::option::Option<&str> { None }
+        }
+    )+};
+}
+
+account_routed_impl!(account_id:
+    CancelOrderRequest, CancelStopOrderRequest, CloseSandboxAccountRequest, GetMarginAttributesRequest,
+    GetMaxLotsRequest, GetOperationsByCursorRequest, GetOrderPriceRequest, GetOrderStateRequest, GetOrdersRequest,
+    GetStopOrdersRequest, OperationsRequest, PortfolioRequest, PositionsRequest, PostOrderAsyncRequest,
+    PostOrderRequest, PostStopOrderRequest, ReplaceOrderRequest, SandboxPayInRequest, WithdrawLimitsRequest,
+);
+
+account_routed_impl!(accounts:
+    OrderStateStreamRequest, PortfolioStreamRequest, PositionsStreamRequest, TradesStreamRequest,
+);
+
+account_routed_impl!(none:
+    AssetRequest, AssetsRequest, CreateFavoriteGroupRequest, DeleteFavoriteGroupRequest, EditFavoritesRequest,
+    FilterOptionsRequest, FindInstrumentRequest, GetAccountsRequest, GetAccruedInterestsRequest,
+    GetAssetFundamentalsRequest, GetAssetReportsRequest, GetBondCouponsRequest, GetBondEventsRequest,
+    GetBrandRequest, GetBrandsRequest, GetCandlesRequest, GetClosePricesRequest, GetConsensusForecastsRequest,
+    GetCountriesRequest, GetDividendsForeignIssuerRequest, GetDividendsRequest, GetFavoriteGroupsRequest,
+    GetFavoritesRequest, GetForecastRequest, GetFuturesMarginRequest, GetInfoRequest, GetLastPricesRequest,
+    GetLastTradesRequest, GetOrderBookRequest, GetSignalsRequest, GetStrategiesRequest, GetTechAnalysisRequest,
+    GetTradingStatusRequest, GetTradingStatusesRequest, GetUserTariffRequest, IndicativesRequest,
+    InstrumentRequest, InstrumentsRequest, OpenSandboxAccountRequest, RiskRatesRequest, TradingSchedulesRequest,
+    MarketDataServerSideStreamRequest,
+);
+
+/// Router of requests between apis with different tokens. Requests with account go through api of this account,
+/// requests without account are balanced between all apis.
+/// Streams with several accounts must have accounts of the same token, otherwise they fail with `INVALID_ARGUMENT`.
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use yatis::router::AccountRouter;
+///     use t_types::*;
+/// #    let token1 = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
+/// #    let token2 = std::env::var("TOKEN2").expect("need to set env var 'TOKEN2'");
+///     let router = AccountRouter::new()
+///         .discover(Api::create_invest_service(token1).unwrap()).await.unwrap()
+///         .discover(Api::create_invest_service(token2).unwrap()).await.unwrap();
+///     for account in router.accounts() {
+///         println!("{:?}", router.request(PortfolioRequest{account_id: account.to_owned(), currency: None}).await);
+///     }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct AccountRouter<T> {
+    apis: Vec<T>,
+    accounts: HashMap<String, usize>,
+    next: AtomicUsize,
+}
+
+impl<T> Default for AccountRouter<T> {
+    fn default() -> Self {
+        Self { apis: Vec::new(), accounts: HashMap::new(), next: AtomicUsize::new(0) }
+    }
+}
+
+impl<T> AccountRouter<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Add api with token for accounts
+    pub fn with_accounts(mut self, api: T, accounts: impl IntoIterator<Item = impl ToString>) -> Self {
+        let idx = self.apis.len();
+        self.apis.push(api);
+        self.accounts.extend(accounts.into_iter().map(|a| (a.to_string(), idx)));
+        self
+    }
+    /// Add api with all accounts of its token
+    pub async fn discover(self, api: T) -> Result<Self, tonic::Status> where T: OwnedSender<GetAccountsRequest, GetAccountsResponse> {
+        let res = api.send(GetAccountsRequest::default()).await?;
+        Ok(self.with_accounts(api, res.accounts.into_iter().map(|a| a.id)))
+    }
+    pub fn accounts(&self) -> impl Iterator<Item = &str> {
+        self.accounts.keys().map(String::as_str)
+    }
+    /// Api for account, or next api for requests without account
+    pub fn api(&self, account: std::option::Option<&str>) -> std::option::Option<&T> {
+        self.index(account).map(|idx| &self.apis[idx])
+    }
+    fn index(&self, account: std::option::Option<&str>) -> std::option::Option<usize> {
+        match account {
+            Some(account) => self.accounts.get(account).copied(),
+            None if self.apis.is_empty() => None,
+            None => Some(self.next.fetch_add(1, Ordering::Relaxed) % self.apis.len()),
+        }
+    }
+    /// Index of api for all accounts of request. Status is returned to caller as is, so it is not boxed
+    #[allow(clippy::result_large_err)]
+    fn route(&self, req: &impl AccountRouted) -> Result<usize, tonic::Status> {
+        let accounts = req.accounts();
+        if accounts.is_empty() {
+            return self.index(None).ok_or_else(|| tonic::Status::unavailable("no api in router"));
+        }
+        let mut route = None;
+        for account in accounts {
+            let idx = self.index(Some(account))
+                .ok_or_else(|| tonic::Status::permission_denied(format!("no token for account {account}")))?;
+            if route.is_some_and(|route| route != idx) {
+                return Err(tonic::Status::invalid_argument("accounts of request have different tokens"));
+            }
+            route = Some(idx);
+        }
+        Ok(route.expect("accounts are not empty"))
+    }
+}
+
+impl<T, Req, Res> OwnedSender<Req, Res> for AccountRouter<T> where T: OwnedSender<Req, Res> + Send + Sync, Req: AccountRouted + Send, Res: Send {
+    /// Api of request is taken from router for request and returned back after it
+    fn send_and_back(mut self, req: Req) -> impl Future<Output = (Self, Result<Res, tonic::Status>)> + Send {
+        Box::pin(async move {
+            let idx = match self.route(&req) {
+                Ok(idx) => idx,
+                Err(status) => return (self, Err(status)),
+            };
+            let api = self.apis.remove(idx);
+            let (api, res) = api.send_and_back(req).await;
+            self.apis.insert(idx, api);
+            (self, res)
+        })
+    }
+    fn send(&self, req: Req) -> impl Future<Output = Result<Res, tonic::Status>> + Send {
+        Box::pin(async move {
+            let api = &self.apis[self.route(&req)?];
+            api.send(req).await
+        })
+    }
+    fn send_with_meta(&self, req: Req) -> impl Future<Output = Result<Response<Res>, tonic::Status>> + Send {
+        Box::pin(async move {
+            let api = &self.apis[self.route(&req)?];
+            api.send_with_meta(req).await
+        })
+    }
+    fn method_name() -> &'static str {
+        T::method_name()
+    }
+}
+
+impl<T, Req, X> StartStream<Req, X> for AccountRouter<T> where T: StartStream<Req, X> + Sync, Req: AccountRouted + Send {
+    fn start_stream<S>(&self, req: Req, sender: S) -> impl Future<Output = Result<StreamHandle<Req>, tonic::Status>> + Send
+    where S: futures::Sink<X> + Unpin + Send + 'static {
+        Box::pin(async move {
+            let api = &self.apis[self.route(&req)?];
+            api.start_stream(req, sender).await
+        })
+    }
+    fn start_stream_with_policy<S>(&self, req: Req, sender: S, policy: ReconnectPolicy) -> impl Future<Output = Result<StreamHandle<Req>, tonic::Status>> + Send
+    where S: futures::Sink<X> + Unpin + Send + 'static {
+        Box::pin(async move {
+            let api = &self.apis[self.route(&req)?];
+            api.start_stream_with_policy(req, sender, policy).await
+        })
+    }
+    fn stream_name() -> &'static str {
+        T::stream_name()
+    }
+}
+
+impl<T: AnyRequestor + Sync> AnyRequestor for AccountRouter<T> {}
+impl<T: AnyStream<StreamResponse> + Sync> AnyStream<StreamResponse> for AccountRouter<T> {}
+
+#[test]
+fn test_route() {
+    let router = AccountRouter::new().with_accounts(1, ["a", "b"]).with_accounts(2, ["c"]);
+    assert_eq!(router.api(PostOrderRequest { account_id: "c".into(), ..Default::default() }.account()), Some(&2));
+    assert_eq!(router.api(PositionsStreamRequest { accounts: vec!["b".into()], ..Default::default() }.account()), Some(&1));
+    assert_eq!(router.api(Some("d")), None);
+    let first = router.api(GetInfoRequest {}.account()).copied();
+    let second = router.api(GetInfoRequest {}.account()).copied();
+    assert_ne!(first, second);
+    let stream = PositionsStreamRequest { accounts: vec!["a".into(), "b".into()], ..Default::default() };
+    assert_eq!(router.route(&stream).unwrap(), 0);
+    let stream = PositionsStreamRequest { accounts: vec!["a".into(), "c".into()], ..Default::default() };
+    assert_eq!(router.route(&stream).unwrap_err().code(), tonic::Code::InvalidArgument);
+    let stream = PositionsStreamRequest { accounts: vec!["a".into(), "d".into()], ..Default::default() };
+    assert_eq!(router.route(&stream).unwrap_err().code(), tonic::Code::PermissionDenied);
+}
+
+#[tokio::test]
+async fn test_send_and_back() {
+    use crate::paper::PaperApi;
+    let router = AccountRouter::new()
+        .with_accounts(PaperApi::new().with_account("a", 100.0), ["a"])
+        .with_accounts(PaperApi::new().with_account("b", 200.0), ["b"]);
+    let req = PortfolioRequest { account_id: "b".into(), currency: None };
+    let (router, res) = OwnedSender::<_, PortfolioResponse>::send_and_back(router, req).await;
+    assert_eq!(res.unwrap().account_id, "b");
+    let req = PortfolioRequest { account_id: "a".into(), currency: None };
+    let (_, res) = OwnedSender::<_, PortfolioResponse>::send_and_back(router, req).await;
+    assert_eq!(res.unwrap().account_id, "a");
+}
@@ -1,19 +1,18 @@
-use tonic::client::Grpc;
-use tonic::transport::{Channel, ClientTlsConfig};
-use tonic::codec::CompressionEncoding::Gzip as GZIP;
-
-use crate::{Api, InvestService, StartStream, StreamResponse, TokenInterceptor};
+use crate::stream::{ReconnectPolicy, StreamHandle};
+use crate::{Api, ApiBuilder, InvestService, StartStream, StreamResponse};
 
 #[derive(Clone)]
 pub struct Sandbox(Api);
 
+impl Sandbox {
+    pub(crate) fn new(api: Api) -> Self {
+        Self(api)
+    }
+}
+
 impl InvestService for Sandbox {
     fn create_invest_service(token: impl ToString) -> Result<Self, tonic::transport::Error> {
-        let tls = ClientTlsConfig::new().with_native_roots();
-        let channel = Channel::from_static("https://sandbox-invest-public-api.tinkoff.ru").tls_config(tls)?.connect_lazy();
-        let serv = tonic::service::interceptor::InterceptedService::new(channel, TokenInterceptor::new(token));
-        let g = Grpc::new(serv).accept_compressed(GZIP).send_compressed(GZIP);
-        Ok(Self(g))
+        ApiBuilder::new(token).build_sandbox()
     }
 }
 
@@ -31,6 +30,17 @@ macro_rules! sandbox_sender_impl {
                 let r = client.$method(req).await.map(|r|r.into_inner());
                 r
             })}
+            fn send_with_meta(&self, req: $req) -> impl std::future::Future<Output = Result<Response<$res>, tonic::Status>> {Box::pin(async move {
+                let mut client = $client::from(self.0.clone());
+                let start = std::time::Instant::now();
+                let (req, sent) = tracked(req);
+                let r = client.$method(req).await?;
+                Ok(Response::new(r, start.elapsed()).with_sent_tracking_id(sent))
+            })}
+            fn method_name() -> &'static str {
+                static NAME: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+                NAME.get_or_init(||crate::limits::grpc_method(stringify!($client), stringify!($method)))
+            }
         }
     )+}
 }
@@ -47,7 +57,7 @@ use crate::t_types::users_service_client::UsersServiceClient;
 use crate::t_types::sandbox_service_client::SandboxServiceClient;
 
 use crate::t_types::*;
-use crate::requestor::{AnyRequestor, OwnedSender};
+use crate::requestor::{tracked, AnyRequestor, OwnedSender, Response};
 
 impl AnyRequestor for Sandbox {}
 
@@ -133,13 +143,23 @@ sandbox_sender_impl![
 
 
 impl<Req, T> StartStream<Req,T> for Sandbox where Api: StartStream<Req, T> + Clone, Req: Send {
-    fn start_stream<S>(&self, req: Req, sender: S) -> impl std::future::Future<Output=Result<tokio::task::JoinHandle<()>, tonic::Status>> + Send
+    fn stream_name() -> &'static str {
+        <Api as StartStream<Req, T>>::stream_name()
+    }
+    fn start_stream<S>(&self, req: Req, sender: S) -> impl std::future::Future<Output=Result<StreamHandle<Req>, tonic::Status>> + Send
     where S: futures::Sink<T> + Unpin + Send + 'static {
         Box::pin(async move {
             let Self(api) = self;
             api.start_stream(req, sender).await
         })
     }
+    fn start_stream_with_policy<S>(&self, req: Req, sender: S, policy: ReconnectPolicy) -> impl std::future::Future<Output=Result<StreamHandle<Req>, tonic::Status>> + Send
+    where S: futures::Sink<T> + Unpin + Send + 'static {
+        Box::pin(async move {
+            let Self(api) = self;
+            api.start_stream_with_policy(req, sender, policy).await
+        })
+    }
 }
 
 impl crate::stream::AnyStream<StreamResponse> for Sandbox {}
\ No newline at end of file
@@ -1,5 +1,6 @@
 //! Traits and implementations for opening server-side streams to API. Implements single method [StartStream::start_stream] for any possible stream-requests
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use crate::t_types::market_data_stream_service_client::MarketDataStreamServiceClient;
 use crate::t_types::operations_stream_service_client::OperationsStreamServiceClient;
 use crate::t_types::orders_stream_service_client::OrdersStreamServiceClient;
@@ -8,6 +9,7 @@ use crate::Api;
 use tokio::task::JoinHandle;
 use log::{warn, error};
 use futures::SinkExt;
+use tokio::sync::watch;
 
 trait PingDelayMs {
     const NET_DELAY: u64 = 2000;
@@ -46,7 +48,284 @@ ping_from_ping_settings!(PortfolioStreamRequest, PositionsStreamRequest, MarketD
 /// Main trait for stream requests
 pub trait StartStream<Req, T> {
     /// Open stream with request. Implementation must reopen stream with same request when connection is lost.
-    fn start_stream<S: futures::Sink<T> + Unpin + Send + 'static>(&self, req: Req, response_sender: S) -> impl std::future::Future<Output=Result<JoinHandle<()>, tonic::Status>> + Send;
+    fn start_stream<S: futures::Sink<T> + Unpin + Send + 'static>(&self, req: Req, response_sender: S) -> impl std::future::Future<Output=Result<StreamHandle<Req>, tonic::Status>> + Send;
+    /// full grpc name of stream, e.g. `tinkoff.public.invest.api.contract.v1.OrdersStreamService/TradesStream`. Used for limits,
+    /// so wrappers must return name of inner stream. Default empty name means no limit of stream
+    fn stream_name() -> &'static str {
+        ""
+    }
+    /// Open stream like [StartStream::start_stream] with custom policy of reconnects.
+    /// Default implementation ignores policy
+    fn start_stream_with_policy<S>(&self, req: Req, response_sender: S, policy: ReconnectPolicy) -> impl std::future::Future<Output=Result<StreamHandle<Req>, tonic::Status>> + Send
+    where S: futures::Sink<T> + Unpin + Send + 'static {
+        let _ = policy;
+        self.start_stream(req, response_sender)
+    }
+}
+
+/// Delays between reconnects of stream: exponential backoff with jitter
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use std::time::Duration;
+///     use yatis::*;
+///     use yatis::stream::ReconnectPolicy;
+///     use t_types::*;
+/// #    let token = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
+///     let api = Api::create_invest_service(token).unwrap();
+///     let policy = ReconnectPolicy::new()
+///         .with_delay(Duration::from_millis(500), Duration::from_secs(30))
+///         .with_deadline(Duration::from_secs(600));
+///     let (s, _r) = futures::channel::mpsc::channel::<StreamResponse>(10);
+///     let handle = api.start_stream_with_policy(PortfolioStreamRequest::default(), s, policy).await.unwrap();
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: f64,
+    jitter: bool,
+    max_attempts: std::option::Option<u32>,
+    deadline: std::option::Option<Duration>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: true,
+            max_attempts: None,
+            deadline: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_delay(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_delay = initial;
+        self.max_delay = max.max(initial);
+        self
+    }
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+    /// Randomize delays from half to full, enabled by default
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+    /// Stop stream after failed attempts in a row
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts.max(1));
+        self
+    }
+    /// Stop stream, if connection is not restored in time
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+    /// Delay after failed attempt (starts from 1)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = crate::retry::exponential(self.initial_delay, self.max_delay, self.multiplier, attempt);
+        if self.jitter { crate::retry::jitter(delay) } else { delay }
+    }
+    /// Whether to stop reconnecting after `attempt` failed attempts during `elapsed` time
+    pub fn gives_up(&self, attempt: u32, elapsed: Duration) -> bool {
+        self.max_attempts.is_some_and(|max| attempt >= max) || self.deadline.is_some_and(|d| elapsed >= d)
+    }
+}
+
+/// Events of stream lifecycle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamEvent {
+    /// Stream is not opened yet
+    Connecting,
+    Connected,
+    /// Connection is lost, stream is reopening
+    Reconnecting { attempt: u32, reason: String },
+    /// Stream is reopened with the same request after reconnect
+    Resubscribed,
+    /// Stream is closed by [StreamHandle::pause] until [StreamHandle::resume]
+    Paused,
+    Stopped(String),
+}
+
+/// Status of running stream
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use yatis::stream::StreamEvent;
+///     use t_types::*;
+/// #    let token = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
+///     let api = Api::create_invest_service(token).unwrap();
+///     let (s, _r) = futures::channel::mpsc::channel::<StreamResponse>(10);
+///     let handle = api.start_stream(PortfolioStreamRequest::default(), s).await.unwrap();
+///     let mut monitor = handle.monitor().clone();
+///     while let Ok(event) = monitor.changed().await {
+///         println!("{event:?}, last message at {:?}", monitor.last_message());
+///         if let StreamEvent::Stopped(_) = event { break }
+///     }
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct StreamMonitor {
+    status: watch::Receiver<StreamEvent>,
+    last_message: Arc<Mutex<std::option::Option<SystemTime>>>,
+}
+
+impl StreamMonitor {
+    pub(crate) fn new() -> (StreamReporter, Self) {
+        let (sender, status) = watch::channel(StreamEvent::Connecting);
+        let last_message = Arc::new(Mutex::new(None));
+        (StreamReporter { status: sender, last_message: last_message.clone() }, Self { status, last_message })
+    }
+    /// Current status. Stream, which task is aborted, is stopped
+    pub fn status(&self) -> StreamEvent {
+        match self.status.has_changed() {
+            Err(_) if !matches!(*self.status.borrow(), StreamEvent::Stopped(_)) => StreamEvent::Stopped("aborted".into()),
+            _ => self.status.borrow().clone(),
+        }
+    }
+    /// Wait for next event. Returns error, when stream task is finished
+    pub async fn changed(&mut self) -> Result<StreamEvent, watch::error::RecvError> {
+        self.status.changed().await?;
+        Ok(self.status.borrow_and_update().clone())
+    }
+    /// Time of last message from server, including pings
+    pub fn last_message(&self) -> std::option::Option<SystemTime> {
+        *self.last_message.lock().unwrap()
+    }
+    /// Receiver of status changes
+    pub fn subscribe(&self) -> watch::Receiver<StreamEvent> {
+        self.status.clone()
+    }
+}
+
+/// Writing side of [StreamMonitor], used by stream task
+#[derive(Debug)]
+pub(crate) struct StreamReporter {
+    status: watch::Sender<StreamEvent>,
+    last_message: Arc<Mutex<std::option::Option<SystemTime>>>,
+}
+
+impl StreamReporter {
+    pub(crate) fn event(&self, event: StreamEvent) {
+        self.status.send_replace(event);
+    }
+    pub(crate) fn message(&self) {
+        *self.last_message.lock().unwrap() = Some(SystemTime::now());
+    }
+}
+
+/// Command from [StreamHandle] to stream task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StreamControl {
+    Running,
+    Paused,
+    Stopped,
+}
+
+/// Handle of running stream. Stream keeps running, when handle is dropped.
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use t_types::*;
+/// #    let token = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
+///     let api = Api::create_invest_service(token).unwrap();
+///     let (s, _r) = futures::channel::mpsc::channel::<StreamResponse>(10);
+///     let handle = api.start_stream(PortfolioStreamRequest{accounts: vec!["first".into()], ..Default::default()}, s).await.unwrap();
+///     handle.modify_request(|req| req.accounts.push("second".into()));
+///     handle.pause();
+///     handle.resume();
+///     println!("{:?}: {:?}", handle.request(), handle.monitor().status());
+///     handle.stop();
+///     handle.join().await.unwrap();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct StreamHandle<Req> {
+    control: watch::Sender<(Req, StreamControl)>,
+    monitor: StreamMonitor,
+    handle: JoinHandle<()>,
+}
+
+impl<Req: Clone> StreamHandle<Req> {
+    pub(crate) fn new(control: watch::Sender<(Req, StreamControl)>, monitor: StreamMonitor, handle: JoinHandle<()>) -> Self {
+        Self { control, monitor, handle }
+    }
+    /// Current request of stream
+    pub fn request(&self) -> Req {
+        self.control.borrow().0.clone()
+    }
+    /// Replace request, running stream is reopened with new request
+    pub fn set_request(&self, req: Req) {
+        self.control.send_modify(|(current, _)| *current = req);
+    }
+    /// Change request, running stream is reopened with changed request
+    pub fn modify_request(&self, f: impl FnOnce(&mut Req)) {
+        self.control.send_modify(|(current, _)| f(current));
+    }
+    /// Close connection, but keep stream task. Stream is reopened by [StreamHandle::resume]
+    pub fn pause(&self) {
+        self.set_control(StreamControl::Paused);
+    }
+    pub fn resume(&self) {
+        self.set_control(StreamControl::Running);
+    }
+    pub fn is_paused(&self) -> bool {
+        self.control.borrow().1 == StreamControl::Paused
+    }
+    /// Gracefully stop stream: stream task reports [StreamEvent::Stopped] and finishes
+    pub fn stop(&self) {
+        self.set_control(StreamControl::Stopped);
+    }
+    /// Abort stream task immediately
+    pub fn abort(&self) {
+        self.handle.abort();
+    }
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+    pub fn monitor(&self) -> &StreamMonitor {
+        &self.monitor
+    }
+    /// Receiver of current request, changed by handle
+    pub(crate) fn watch_request(&self) -> watch::Receiver<(Req, StreamControl)> {
+        self.control.subscribe()
+    }
+    /// Wait for stream task to finish
+    pub async fn join(self) -> Result<(), tokio::task::JoinError> {
+        self.handle.await
+    }
+    fn set_control(&self, control: StreamControl) {
+        self.control.send_if_modified(|(_, current)| {
+            let changed = *current != control && *current != StreamControl::Stopped;
+            if changed {
+                *current = control;
+            }
+            changed
+        });
+    }
+}
+
+/// Next message of stream, closed stream has no messages
+async fn next_message<T>(receiver: &mut std::option::Option<tonic::Streaming<T>>) -> Result<std::option::Option<T>, tonic::Status> {
+    match receiver {
+        Some(receiver) => receiver.message().await,
+        None => Ok(None),
+    }
 }
 
 impl AnyStream<crate::StreamResponse> for Api {}
@@ -57,38 +336,124 @@ macro_rules! start_stream_impl {
         pub trait AnyStream<T>: $(StartStream<$req, T> +)+ Send where T: $( From<$res> +)+ Send + 'static {}
         $(
         impl<T> StartStream<$req, T> for Api where T: From<$res> + Send + 'static {
-            fn start_stream<S>(&self, req: $req, mut sender: S) -> impl std::future::Future<Output=Result<JoinHandle<()>, tonic::Status>> 
+            fn stream_name() -> &'static str {
+                static NAME: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+                NAME.get_or_init(||crate::limits::grpc_method(stringify!($client), stringify!($method)))
+            }
+            fn start_stream<S>(&self, req: $req, sender: S) -> impl std::future::Future<Output=Result<StreamHandle<$req>, tonic::Status>> 
+            where S: futures::Sink<T> + Unpin + Send + 'static {
+                self.start_stream_with_policy(req, sender, ReconnectPolicy::default())
+            }
+            fn start_stream_with_policy<S>(&self, req: $req, mut sender: S, policy: ReconnectPolicy) -> impl std::future::Future<Output=Result<StreamHandle<$req>, tonic::Status>> 
             where S: futures::Sink<T> + Unpin + Send + 'static { Box::pin(async move {
                 let this = self.clone();
                 let timeout = Duration::from_millis(req.delay_ms());
+                let (reporter, monitor) = StreamMonitor::new();
                 let mut client = $client::from(this.clone());
-                let mut receiver = client.$method(req.clone()).await?.into_inner();
+                let mut receiver = Some(client.$method(req.clone()).await?.into_inner());
+                reporter.event(StreamEvent::Connected);
+                let (control, mut commands) = watch::channel((req, StreamControl::Running));
                 let handle = tokio::spawn(async move {
+                    let mut attempt = 0;
+                    let mut outage = std::time::Instant::now();
+                    let mut paused = false;
+                    let mut detached = false;
                     loop {
-                        match tokio::time::timeout(timeout, receiver.message()).await {
-                            Ok(Ok(Some(response))) => {
-                                if sender.send(response.into()).await.is_err() { break; }
-                                continue;
+                        let reason = tokio::select! {
+                            biased;
+                            changed = commands.changed(), if !detached => {
+                                if changed.is_err() {
+                                    detached = true;
+                                    if paused {
+                                        reporter.event(StreamEvent::Stopped("handle of paused stream is dropped".into()));
+                                        break;
+                                    }
+                                    continue;
+                                }
+                                let state = commands.borrow_and_update().1;
+                                match state {
+                                    StreamControl::Stopped => {
+                                        reporter.event(StreamEvent::Stopped("stopped by handle".into()));
+                                        break;
+                                    }
+                                    StreamControl::Paused => {
+                                        if !paused {
+                                            paused = true;
+                                            receiver = None;
+                                            reporter.event(StreamEvent::Paused);
+                                        }
+                                        continue;
+                                    }
+                                    StreamControl::Running if paused => "resumed".to_owned(),
+                                    StreamControl::Running => "request is changed".to_owned(),
+                                }
+                            }
+                            message = tokio::time::timeout(timeout, next_message(&mut receiver)), if !paused => match message {
+                                Ok(Ok(Some(response))) => {
+                                    reporter.message();
+                                    if sender.send(response.into()).await.is_err() {
+                                        reporter.event(StreamEvent::Stopped("receiver is closed".into()));
+                                        break;
+                                    }
+                                    continue;
+                                },
+                                Ok(Ok(None)) => "none received".to_owned(),
+                                Ok(Err(e)) => format!("err {e:?}"),
+                                Err(_) => "timeout".to_owned(),
                             },
-                            Ok(Ok(None)) => warn!("none received, reconnecting..."),
-                            Ok(Err(e)) => log::error!("err {e:?}, reconnecting..."),
-                            Err(_) => warn!("timeout, reconnecting..."),
+                        };
+                        if paused {
+                            paused = false;
+                            attempt = 0;
+                        } else if attempt == 0 {
+                            outage = std::time::Instant::now();
+                        } else if policy.gives_up(attempt, outage.elapsed()) {
+                            error!("{reason}, stream is stopped after {attempt} attempts to reconnect");
+                            reporter.event(StreamEvent::Stopped(format!("reconnect failed: {reason}")));
+                            break;
+                        } else {
+                            let delay = tokio::time::sleep(policy.delay(attempt));
+                            tokio::pin!(delay);
+                            let interrupted = loop {
+                                tokio::select! {
+                                    _ = &mut delay => break false,
+                                    changed = commands.changed(), if !detached => match changed {
+                                        Ok(()) => break true,
+                                        Err(_) => detached = true,
+                                    },
+                                }
+                            };
+                            // changed request is sent right now, stop and pause are processed by the next iteration
+                            if interrupted && commands.borrow().1 != StreamControl::Running {
+                                commands.mark_changed();
+                                continue;
+                            }
                         }
-                        let mut client = $client::from(this.clone());    
-                        let fut = client.$method(req.clone());
-                        match tokio::time::timeout(timeout, fut).await {
+                        warn!("{reason}, reconnecting...");
+                        // old stream holds slot of limits
+                        drop(receiver.take());
+                        attempt += 1;
+                        reporter.event(StreamEvent::Reconnecting { attempt, reason });
+                        let req = commands.borrow().0.clone();
+                        let mut client = $client::from(this.clone());
+                        match tokio::time::timeout(timeout, client.$method(req)).await {
                             Ok(Ok(x)) => {
-                                receiver = x.into_inner();
+                                receiver = Some(x.into_inner());
+                                attempt = 0;
+                                reporter.event(StreamEvent::Resubscribed);
                             }
                             Ok(Err(x)) => {
                                 error!("err on reconnect: {x:?}");
-                                tokio::time::sleep(timeout).await;
-                            },
-                            Err(_) => warn!("timeout on reconnect"),
+                                receiver = None;
+                            }
+                            Err(_) => {
+                                warn!("timeout on reconnect");
+                                receiver = None;
+                            }
                         }
                     };
                 });
-                Ok(handle)
+                Ok(StreamHandle::new(control, monitor, handle))
             })}
         }
     )+}
@@ -100,4 +465,50 @@ start_stream_impl![
     PortfolioStreamResponse = OperationsStreamServiceClient:portfolio_stream(PortfolioStreamRequest),
     PositionsStreamResponse = OperationsStreamServiceClient:positions_stream(PositionsStreamRequest),
     MarketDataResponse = MarketDataStreamServiceClient:market_data_server_side_stream(MarketDataServerSideStreamRequest),
-];
\ No newline at end of file
+];
+#[test]
+fn test_monitor() {
+    let (reporter, monitor) = StreamMonitor::new();
+    assert_eq!(monitor.status(), StreamEvent::Connecting);
+    reporter.event(StreamEvent::Reconnecting { attempt: 1, reason: "timeout".into() });
+    assert_eq!(monitor.status(), StreamEvent::Reconnecting { attempt: 1, reason: "timeout".into() });
+    assert!(monitor.last_message().is_none());
+    reporter.message();
+    assert!(monitor.last_message().is_some());
+    drop(reporter);
+    assert_eq!(monitor.status(), StreamEvent::Stopped("aborted".into()));
+}
+
+#[test]
+fn test_reconnect_policy() {
+    let policy = ReconnectPolicy::new()
+        .with_delay(Duration::from_millis(100), Duration::from_millis(500))
+        .with_jitter(false)
+        .with_max_attempts(5);
+    assert_eq!(policy.delay(1), Duration::from_millis(100));
+    assert_eq!(policy.delay(3), Duration::from_millis(400));
+    assert_eq!(policy.delay(10), Duration::from_millis(500));
+    assert!(!policy.gives_up(4, Duration::from_secs(100)));
+    assert!(policy.gives_up(5, Duration::ZERO));
+    let jittered = ReconnectPolicy::new().with_deadline(Duration::from_secs(10)).delay(2);
+    assert!(jittered >= Duration::from_secs(1) && jittered <= Duration::from_secs(2));
+    assert!(ReconnectPolicy::new().with_deadline(Duration::from_secs(10)).gives_up(1, Duration::from_secs(10)));
+}
+
+#[tokio::test]
+async fn test_stream_handle() {
+    let (control, mut commands) = watch::channel((PortfolioStreamRequest::default(), StreamControl::Running));
+    let (_reporter, monitor) = StreamMonitor::new();
+    let handle = StreamHandle::new(control, monitor, tokio::spawn(async {}));
+    handle.modify_request(|req| req.accounts.push("a".into()));
+    assert!(commands.has_changed().unwrap());
+    assert_eq!(commands.borrow_and_update().0.accounts, vec!["a".to_string()]);
+    handle.resume();
+    assert!(!commands.has_changed().unwrap());
+    handle.pause();
+    assert!(handle.is_paused());
+    handle.stop();
+    handle.resume();
+    assert_eq!(commands.borrow_and_update().1, StreamControl::Stopped);
+    handle.join().await.unwrap();
+}
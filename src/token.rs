@@ -0,0 +1,136 @@
+//! Sources of token for [crate::TokenInterceptor]. Token is taken from provider on every request,
+//! so new token is used by existing apis, pools and streams after reconnect.
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Source of token
+pub trait TokenProvider: Send + Sync {
+    /// Current token, `None` if token is not available
+    fn token(&self) -> Option<String>;
+}
+
+impl TokenProvider for String {
+    fn token(&self) -> Option<String> {
+        Some(self.clone())
+    }
+}
+
+/// In-memory token, that can be replaced. Clones share the same token
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use yatis::token::TokenHandle;
+/// #    let token = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
+///     let handle = TokenHandle::new(token);
+///     let api = ApiBuilder::with_interceptor(TokenInterceptor::with_provider(handle.clone())).build().unwrap();
+///     println!("{:?}", api.request(t_types::GetInfoRequest{}).await);
+///     handle.set("new token");
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TokenHandle(Arc<RwLock<String>>);
+
+impl TokenHandle {
+    pub fn new(token: impl ToString) -> Self {
+        Self(Arc::new(RwLock::new(token.to_string())))
+    }
+    pub fn set(&self, token: impl ToString) {
+        *self.0.write().unwrap() = token.to_string();
+    }
+}
+
+impl TokenProvider for TokenHandle {
+    fn token(&self) -> Option<String> {
+        Some(self.0.read().unwrap().clone())
+    }
+}
+
+/// Token from environment variable, read on every request
+#[derive(Debug, Clone)]
+pub struct EnvToken(String);
+
+impl EnvToken {
+    pub fn new(var: impl ToString) -> Self {
+        Self(var.to_string())
+    }
+}
+
+impl TokenProvider for EnvToken {
+    fn token(&self) -> Option<String> {
+        std::env::var(&self.0).ok()
+    }
+}
+
+/// Token from file. Token is cached, file is checked for changes not more often than check interval
+/// (10 seconds by default) and is reread when it is changed
+#[derive(Debug)]
+pub struct FileToken {
+    path: PathBuf,
+    check_interval: Duration,
+    cache: Mutex<FileTokenCache>,
+}
+
+#[derive(Debug, Default)]
+struct FileTokenCache {
+    checked: Option<Instant>,
+    /// modification time and size of read file
+    version: Option<(SystemTime, u64)>,
+    token: Option<String>,
+}
+
+impl FileToken {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), check_interval: Duration::from_secs(10), cache: Mutex::default() }
+    }
+    /// Min interval between checks of file
+    pub fn with_check_interval(mut self, interval: Duration) -> Self {
+        self.check_interval = interval;
+        self
+    }
+}
+
+impl TokenProvider for FileToken {
+    fn token(&self) -> Option<String> {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.checked.is_some_and(|checked| checked.elapsed() < self.check_interval) {
+            return cache.token.clone();
+        }
+        cache.checked = Some(Instant::now());
+        let meta = match std::fs::metadata(&self.path) {
+            Ok(meta) => meta,
+            Err(e) => {
+                log::warn!("cannot read token file {:?}: {e}", self.path);
+                return cache.token.clone();
+            }
+        };
+        let version = (meta.modified().unwrap_or(SystemTime::UNIX_EPOCH), meta.len());
+        if cache.version != Some(version) {
+            match std::fs::read_to_string(&self.path) {
+                Ok(token) => {
+                    cache.version = Some(version);
+                    cache.token = Some(token.trim().to_owned());
+                }
+                Err(e) => log::warn!("cannot read token file {:?}: {e}", self.path),
+            }
+        }
+        cache.token.clone()
+    }
+}
+
+#[test]
+fn test_file_token() {
+    let path = std::env::temp_dir().join(format!("yatis-token-{}", std::process::id()));
+    std::fs::write(&path, "first\n").unwrap();
+    let cached = FileToken::new(&path);
+    let provider = FileToken::new(&path).with_check_interval(Duration::ZERO);
+    assert_eq!(cached.token().as_deref(), Some("first"));
+    assert_eq!(provider.token().as_deref(), Some("first"));
+    std::fs::write(&path, "second-token").unwrap();
+    assert_eq!(cached.token().as_deref(), Some("first"));
+    assert_eq!(provider.token().as_deref(), Some("second-token"));
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(provider.token().as_deref(), Some("second-token"));
+}
@@ -0,0 +1,352 @@
+//! Record and playback of unary requests. [Recording] writes every request with its response (or error)
+//! into cassette file, [Playback] answers the same requests from cassette without network.
+use std::fs::File;
+use std::future::Future;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use log::error;
+use prost::Message;
+
+use crate::record::MessageWriter;
+use crate::requestor::{AnyRequestor, OwnedSender, Response};
+use crate::stream::{AnyStream, ReconnectPolicy, StartStream, StreamHandle};
+use crate::{Api, StreamResponse};
+
+fn type_name<T>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// Recorded request with response or error
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Interaction {
+    /// grpc method, e.g. `/tinkoff.public.invest.api.contract.v1.UsersService/GetAccounts`
+    #[prost(string, tag = "1")]
+    pub method: String,
+    #[prost(string, tag = "2")]
+    pub request_type: String,
+    #[prost(bytes = "vec", tag = "3")]
+    pub request: Vec<u8>,
+    #[prost(string, tag = "4")]
+    pub response_type: String,
+    #[prost(bytes = "vec", tag = "5")]
+    pub response: Vec<u8>,
+    /// grpc code of error, 0 for response
+    #[prost(int32, tag = "6")]
+    pub code: i32,
+    /// message of error status
+    #[prost(string, tag = "7")]
+    pub message: String,
+    /// metadata `message` of error status, description of error from server
+    #[prost(string, tag = "8")]
+    pub description: String,
+}
+
+impl Interaction {
+    fn new<Req, Res: Message>(method: &str, request: Vec<u8>, res: Result<&Res, &tonic::Status>) -> Self {
+        let mut interaction = Self {
+            method: method.to_owned(),
+            request_type: type_name::<Req>().to_owned(),
+            request,
+            response_type: type_name::<Res>().to_owned(),
+            ..Default::default()
+        };
+        match res {
+            Ok(res) => interaction.response = res.encode_to_vec(),
+            Err(status) => {
+                interaction.code = status.code() as i32;
+                interaction.message = status.message().to_owned();
+                interaction.description = status.metadata().get("message").and_then(|v| v.to_str().ok()).unwrap_or_default().to_owned();
+            }
+        }
+        interaction
+    }
+    /// Recorded response or error
+    fn result<Res: Message + Default>(&self) -> Result<Res, Box<tonic::Status>> {
+        if self.code != 0 {
+            let mut status = tonic::Status::new(self.code.into(), self.message.clone());
+            if let Ok(description) = self.description.parse() {
+                status.metadata_mut().insert("message", description);
+            }
+            return Err(Box::new(status));
+        }
+        Res::decode(self.response.as_slice()).map_err(|e| Box::new(tonic::Status::data_loss(format!("cannot decode {}: {e}", self.response_type))))
+    }
+}
+
+/// Read all interactions of cassette
+pub fn read_cassette(path: impl AsRef<Path>) -> std::io::Result<Vec<Interaction>> {
+    crate::record::read_messages(path)
+}
+
+/// Wrapper of api, that writes every unary request and its result into cassette.
+/// Cassette is written by background thread, see [Recording::flush]. Clones write into the same file.
+/// Errors of writing are logged, requests are not failed by them.
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use yatis::vcr::Recording;
+///     use t_types::*;
+/// #    let token = std::env::var("TOKEN").expect("need to set env var 'TOKEN'");
+///     let api = Recording::create(Api::create_invest_service(token).unwrap(), "accounts.cassette").unwrap();
+///     let accounts: GetAccountsResponse = api.request(GetAccountsRequest { status: None }).await.unwrap();
+///     api.flush().await;
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Recording<T> {
+    inner: T,
+    file: MessageWriter<Interaction>,
+}
+
+impl<T> Recording<T> {
+    /// Create cassette file (existing file is truncated)
+    pub fn create(inner: T, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self { inner, file: MessageWriter::new(File::create(path)?) })
+    }
+    /// Append to existing cassette file
+    pub fn append(inner: T, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(Self { inner, file: MessageWriter::new(file) })
+    }
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+    /// Wait until all recorded interactions are written into cassette
+    pub async fn flush(&self) {
+        // canceled notification also means that writer is done
+        let _ = self.file.flush().await;
+    }
+    fn write(&self, interaction: Interaction) {
+        let method = interaction.method.clone();
+        if let Err(e) = self.file.write(interaction) {
+            error!("cannot record {method}: {e}");
+        }
+    }
+}
+
+impl<T, Req, Res> OwnedSender<Req, Res> for Recording<T>
+where T: OwnedSender<Req, Res> + Send + Sync, Api: OwnedSender<Req, Res>, Req: Message, Res: Message {
+    fn send_and_back(self, req: Req) -> impl Future<Output = (Self, Result<Res, tonic::Status>)> + Send {
+        Box::pin(async move {
+            let request = req.encode_to_vec();
+            let Self { inner, file } = self;
+            let (inner, res) = inner.send_and_back(req).await;
+            let this = Self { inner, file };
+            this.write(Interaction::new::<Req, Res>(Self::method_name(), request, res.as_ref()));
+            (this, res)
+        })
+    }
+    fn send(&self, req: Req) -> impl Future<Output = Result<Res, tonic::Status>> + Send {
+        Box::pin(async move {
+            let request = req.encode_to_vec();
+            let res = self.inner.send(req).await;
+            self.write(Interaction::new::<Req, Res>(Self::method_name(), request, res.as_ref()));
+            res
+        })
+    }
+    fn send_with_meta(&self, req: Req) -> impl Future<Output = Result<Response<Res>, tonic::Status>> + Send {
+        Box::pin(async move {
+            let request = req.encode_to_vec();
+            let res = self.inner.send_with_meta(req).await;
+            self.write(Interaction::new::<Req, Res>(Self::method_name(), request, res.as_ref().map(|r| &r.message)));
+            res
+        })
+    }
+    fn method_name() -> &'static str {
+        <Api as OwnedSender<Req, Res>>::method_name()
+    }
+}
+
+impl<T, Req, X> StartStream<Req, X> for Recording<T> where T: StartStream<Req, X> + Sync, Req: Send {
+    fn start_stream<S>(&self, req: Req, sender: S) -> impl Future<Output = Result<StreamHandle<Req>, tonic::Status>> + Send
+    where S: futures::Sink<X> + Unpin + Send + 'static {
+        self.inner.start_stream(req, sender)
+    }
+    fn start_stream_with_policy<S>(&self, req: Req, sender: S, policy: ReconnectPolicy) -> impl Future<Output = Result<StreamHandle<Req>, tonic::Status>> + Send
+    where S: futures::Sink<X> + Unpin + Send + 'static {
+        self.inner.start_stream_with_policy(req, sender, policy)
+    }
+    fn stream_name() -> &'static str {
+        T::stream_name()
+    }
+}
+
+impl<T: AnyRequestor + Sync> AnyRequestor for Recording<T> {}
+impl<T: AnyStream<StreamResponse> + Sync> AnyStream<StreamResponse> for Recording<T> {}
+
+/// Matcher of recorded interaction and encoded request
+type Matcher = Arc<dyn Fn(&Interaction, &[u8]) -> bool + Send + Sync>;
+
+#[derive(Debug, Default)]
+struct PlaybackState {
+    interactions: Vec<Interaction>,
+    used: Vec<bool>,
+    unmatched: Vec<String>,
+}
+
+impl PlaybackState {
+    /// First unused interaction with the same method and matched request. When all of them are used, the last one is repeated
+    fn find(&mut self, method: &str, matches: impl Fn(&Interaction) -> bool) -> Option<&Interaction> {
+        let matched: Vec<_> = (0..self.interactions.len())
+            .filter(|&i| self.interactions[i].method == method && matches(&self.interactions[i]))
+            .collect();
+        let i = matched.iter().copied().find(|&i| !self.used[i]).or(matched.last().copied())?;
+        self.used[i] = true;
+        Some(&self.interactions[i])
+    }
+}
+
+/// Api, that answers unary requests from cassette, recorded by [Recording].
+/// Request without recorded answer fails with `NOT_FOUND` and is logged as error, all of them are available by [Playback::unmatched].
+///
+/// By default requests are matched by exact encoded bytes, so requests with generated fields (e.g. `order_id` of
+/// [PostOrderRequest](crate::t_types::PostOrderRequest) or timestamps relative to now) don't match recorded ones.
+/// Such requests need custom matcher, see [Playback::with_matcher].
+/// # Examples:
+/// ```rust
+/// # #[tokio::main]
+/// # async fn main() {
+///     use yatis::*;
+///     use yatis::vcr::Playback;
+///     use t_types::*;
+/// #   let path = std::env::temp_dir().join("yatis-playback-doc.cassette");
+/// #   std::fs::write(&path, b"").unwrap();
+///     let api = Playback::open(&path).unwrap();
+///     let res: Result<GetAccountsResponse, _> = api.request(GetAccountsRequest { status: None }).await;
+///     assert!(res.is_err());
+///     assert_eq!(api.unmatched().len(), 1);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Playback {
+    state: Arc<Mutex<PlaybackState>>,
+    matcher: Option<Matcher>,
+}
+
+impl std::fmt::Debug for Playback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Playback")
+            .field("state", &self.state)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Playback {
+    /// Playback of cassette file
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self::new(read_cassette(path)?))
+    }
+    pub fn new(interactions: Vec<Interaction>) -> Self {
+        let used = vec![false; interactions.len()];
+        Self { state: Arc::new(Mutex::new(PlaybackState { interactions, used, unmatched: Vec::new() })), matcher: None }
+    }
+    /// Matcher of requests instead of exact bytes. It gets recorded interaction of the same method and encoded request.
+    /// # Examples:
+    /// ```rust
+    ///     use prost::Message;
+    ///     use yatis::vcr::Playback;
+    ///     use yatis::t_types::PostOrderRequest;
+    ///     // generated idempotency key of orders is ignored
+    ///     let api = Playback::new(Vec::new()).with_matcher(|recorded, request| {
+    ///         if !recorded.method.ends_with("/PostOrder") {
+    ///             return recorded.request == request;
+    ///         }
+    ///         let decode = |bytes: &[u8]| PostOrderRequest::decode(bytes).map(|r| PostOrderRequest { order_id: String::new(), ..r }).ok();
+    ///         decode(&recorded.request) == decode(request)
+    ///     });
+    /// ```
+    pub fn with_matcher(mut self, matcher: impl Fn(&Interaction, &[u8]) -> bool + Send + Sync + 'static) -> Self {
+        self.matcher = Some(Arc::new(matcher));
+        self
+    }
+    /// Descriptions of requests without recorded answer
+    pub fn unmatched(&self) -> Vec<String> {
+        self.state.lock().unwrap().unmatched.clone()
+    }
+    /// Count of recorded interactions, that are not requested yet
+    pub fn unused(&self) -> usize {
+        self.state.lock().unwrap().used.iter().filter(|used| !**used).count()
+    }
+    fn answer<Req: Message, Res: Message + Default>(&self, method: &str, req: Req) -> Result<Res, Box<tonic::Status>> {
+        let mut state = self.state.lock().unwrap();
+        let request = req.encode_to_vec();
+        let matches = |interaction: &Interaction| match &self.matcher {
+            Some(matcher) => matcher(interaction, &request),
+            None => interaction.request == request,
+        };
+        if let Some(interaction) = state.find(method, matches) {
+            return interaction.result();
+        }
+        let description = format!("{method} {}: {req:?}", type_name::<Req>());
+        error!("no recorded answer for {description}");
+        state.unmatched.push(description.clone());
+        Err(Box::new(tonic::Status::not_found(format!("no recorded answer for {description}"))))
+    }
+}
+
+impl<Req, Res> OwnedSender<Req, Res> for Playback where Api: OwnedSender<Req, Res>, Req: Message, Res: Message + Default {
+    fn send_and_back(self, req: Req) -> impl Future<Output = (Self, Result<Res, tonic::Status>)> + Send {
+        let res = self.answer(Self::method_name(), req).map_err(|e| *e);
+        async move { (self, res) }
+    }
+    fn send(&self, req: Req) -> impl Future<Output = Result<Res, tonic::Status>> + Send {
+        let res = self.answer(Self::method_name(), req).map_err(|e| *e);
+        async move { res }
+    }
+    fn method_name() -> &'static str {
+        <Api as OwnedSender<Req, Res>>::method_name()
+    }
+}
+
+impl AnyRequestor for Playback {}
+
+#[tokio::test]
+async fn test_vcr() {
+    use crate::paper::PaperApi;
+    use crate::t_types::*;
+    use crate::Requestor;
+    let path = std::env::temp_dir().join(format!("yatis-test-vcr-{}.cassette", uuid::Uuid::new_v4()));
+    let api = Recording::create(PaperApi::new().with_account("acc", 1000.0), &path).unwrap();
+    let order = PostOrderRequest { instrument_id: "x".into(), account_id: "acc".into(), quantity: 1, order_type: OrderType::Market.into(), ..Default::default() };
+    let accounts: GetAccountsResponse = api.request(GetAccountsRequest { status: None }).await.unwrap();
+    let err = Requestor::<_, PostOrderResponse>::request(&api, order.clone()).await.unwrap_err();
+    let res: crate::requestor::Response<GetAccountsResponse> = api.request_with_meta(GetAccountsRequest { status: None }).await.unwrap();
+    assert_eq!(res.message, accounts);
+    let (api, res) = OwnedSender::<_, GetAccountsResponse>::send_and_back(api, GetAccountsRequest { status: None }).await;
+    assert_eq!(res.unwrap(), accounts);
+    api.flush().await;
+
+    let interactions = read_cassette(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    let playback = Playback::new(interactions.clone());
+    assert_eq!(playback.unused(), 4);
+    let res: GetAccountsResponse = playback.request(GetAccountsRequest { status: None }).await.unwrap();
+    assert_eq!(res, accounts);
+    let e = Requestor::<_, PostOrderResponse>::request(&playback, order.clone()).await.unwrap_err();
+    assert_eq!((e.code(), e.message()), (err.code(), err.message()));
+    assert_eq!(crate::Error::from(e).message, crate::Error::from(err.clone()).message);
+    let _: GetAccountsResponse = playback.request(GetAccountsRequest { status: None }).await.unwrap();
+    let _: GetAccountsResponse = playback.request(GetAccountsRequest { status: None }).await.unwrap();
+    // repeated request gets the last answer
+    let _: GetAccountsResponse = playback.request(GetAccountsRequest { status: None }).await.unwrap();
+    assert_eq!(playback.unused(), 0);
+    assert!(playback.unmatched().is_empty());
+    let res: Result<GetInfoResponse, _> = playback.request(GetInfoRequest {}).await;
+    assert_eq!(res.unwrap_err().code(), tonic::Code::NotFound);
+    assert_eq!(playback.unmatched().len(), 1);
+
+    // generated idempotency key doesn't match by default
+    let order = PostOrderRequest { order_id: "generated".into(), ..order };
+    let e = Requestor::<_, PostOrderResponse>::request(&playback, order.clone()).await.unwrap_err();
+    assert_eq!(e.code(), tonic::Code::NotFound);
+    let playback = Playback::new(interactions).with_matcher(|recorded, request| {
+        let decode = |bytes: &[u8]| PostOrderRequest::decode(bytes).map(|r| PostOrderRequest { order_id: String::new(), ..r }).ok();
+        recorded.request == request || recorded.method.ends_with("/PostOrder") && decode(&recorded.request) == decode(request)
+    });
+    let e = Requestor::<_, PostOrderResponse>::request(&playback, order).await.unwrap_err();
+    assert_eq!(e.code(), err.code());
+}